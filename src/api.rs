@@ -1,16 +1,25 @@
 use crate::{
-    check::{check_image, ImgCheck, ImgChecker},
-    config::{CheckPhase, ServerConfig},
-    img::{
-        delete_img_files, get_filenames_as_img_id, get_img, read_img, resize_image, save_img,
-        ImgType, SaveImageResult,
+    check::ImgChecker,
+    chunked_upload::{
+        append_chunk, create_session, part_path, read_session, remove_session, ChunkError,
+        UploadId, UploadLock, UploadSession,
     },
+    config::ServerConfig,
+    img::{delete_img_files, get_filenames_as_img_id, get_img, read_img, read_img_file, ImgType},
     notification::{
-        internal_messages::{ChatMessage, ImageDeleted, ImageUploaded, LobbyDeleted, RoomDeleted},
+        internal_messages::{
+            ChatMessage, GetPresence, ImageDeleted, ImagesDeleted, LobbyDeleted, RoomDeleted,
+        },
         server::NotifyServer,
     },
-    permission::check,
-    public_messages::api::{ChatMessageRequest, Success, UploadRequest, UploadResult},
+    permission::{authorize, check, rate_limit::RateLimited, Action},
+    public_messages::api::{
+        ChatMessageRequest, DeleteBatchItemResult, DeleteBatchRequest, DeleteRoomBatchRequest,
+        ImgRef, Success, UploadBatchItemResult, UploadBatchRequest, UploadRequest, UploadResult,
+        UploadSessionRequest, UploadSessionStatus, UploadUrlRequest,
+    },
+    upload::{check_size, process_upload, UploadError},
+    url_upload::fetch_img,
     utils::{get_foldernames_as_uuid, get_session_id},
     ImgId, LobbyId, RoomId,
 };
@@ -23,10 +32,16 @@ use actix_web::{
     web::{self, Data, Json},
     HttpRequest, HttpResponse, Responder,
 };
+use futures_util::{stream, StreamExt as _};
+use image::ImageFormat;
 use log::{debug, warn};
-use std::{fs, path::Path};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs,
+    path::Path,
+};
 
-#[get("/list/{lobby_id}")]
+#[get("/list/{lobby_id}", wrap = "RateLimited(Action::GetRoomList)")]
 pub async fn get_room_list(
     info: web::Path<(LobbyId,)>,
     cfg: Data<ServerConfig>,
@@ -35,7 +50,9 @@ pub async fn get_room_list(
     let lobby_id = info.0.to_string();
 
     // check permission
-    if let Some(err) = check(&cfg.permissions.get_room_list, &req, &info.into_inner()).await {
+    if let Some(err) =
+        check(&cfg.permissions, Action::GetRoomList, &req, &info.into_inner()).await
+    {
         return err;
     }
 
@@ -45,7 +62,7 @@ pub async fn get_room_list(
     HttpResponse::Ok().json(filenames)
 }
 
-#[get("/list/{lobby_id}/{room_id}")]
+#[get("/list/{lobby_id}/{room_id}", wrap = "RateLimited(Action::GetRoomImgList)")]
 pub async fn get_room_img_list(
     info: web::Path<(LobbyId, RoomId)>,
     cfg: Data<ServerConfig>,
@@ -55,7 +72,9 @@ pub async fn get_room_img_list(
     let room_id = info.1.to_string();
 
     // check permission
-    if let Some(err) = check(&cfg.permissions.get_room_img_list, &req, &info.into_inner()).await {
+    if let Some(err) =
+        check(&cfg.permissions, Action::GetRoomImgList, &req, &info.into_inner()).await
+    {
         return err;
     }
 
@@ -66,7 +85,7 @@ pub async fn get_room_img_list(
     HttpResponse::Ok().json(get_filenames_as_img_id(&folder_path).unwrap_or_default())
 }
 
-#[get("/img/thumb/{lobby_id}/{room_id}/{img_id}")]
+#[get("/img/thumb/{lobby_id}/{room_id}/{img_id}", wrap = "RateLimited(Action::GetImgThumb)")]
 pub async fn get_img_thumb(
     info: web::Path<(LobbyId, RoomId, ImgId)>,
     cfg: Data<ServerConfig>,
@@ -75,13 +94,13 @@ pub async fn get_img_thumb(
     let params = info.into_inner();
 
     // check permission
-    if let Some(err) = check(&cfg.permissions.get_img_thumb, &req, &params).await {
+    if let Some(err) = check(&cfg.permissions, Action::GetImgThumb, &req, &params).await {
         return err;
     }
     get_img(ImgType::Thumb, &params, &cfg.images_storage_path)
 }
 
-#[get("/img/{lobby_id}/{room_id}/{img_id}")]
+#[get("/img/{lobby_id}/{room_id}/{img_id}", wrap = "RateLimited(Action::GetImgBig)")]
 pub async fn get_img_big(
     info: web::Path<(LobbyId, RoomId, ImgId)>,
     cfg: Data<ServerConfig>,
@@ -90,13 +109,29 @@ pub async fn get_img_big(
     let params = info.into_inner();
 
     // check permission
-    if let Some(err) = check(&cfg.permissions.get_img_big, &req, &params).await {
+    if let Some(err) = check(&cfg.permissions, Action::GetImgBig, &req, &params).await {
         return err;
     }
 
     get_img(ImgType::Big, &params, &cfg.images_storage_path)
 }
 
+#[get("/img/original/{lobby_id}/{room_id}/{img_id}", wrap = "RateLimited(Action::GetImgOriginal)")]
+pub async fn get_img_original(
+    info: web::Path<(LobbyId, RoomId, ImgId)>,
+    cfg: Data<ServerConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    let params = info.into_inner();
+
+    // check permission
+    if let Some(err) = check(&cfg.permissions, Action::GetImgOriginal, &req, &params).await {
+        return err;
+    }
+
+    get_img(ImgType::Original, &params, &cfg.images_storage_path)
+}
+
 #[options("/{tail:.*}")]
 pub async fn handle_options() -> impl Responder {
     HttpResponse::Ok()
@@ -104,7 +139,7 @@ pub async fn handle_options() -> impl Responder {
         .finish()
 }
 
-#[post("/upload/{lobby_id}/{room_id}")]
+#[post("/upload/{lobby_id}/{room_id}", wrap = "RateLimited(Action::UploadImg)")]
 pub async fn upload_img(
     info: web::Path<(LobbyId, RoomId)>,
     form: MultipartForm<UploadRequest>,
@@ -117,97 +152,249 @@ pub async fn upload_img(
     let room_id = info.1;
 
     // check permission
-    if let Some(err) = check(&cfg.permissions.upload_img, &req, &info.into_inner()).await {
+    if let Some(err) = check(&cfg.permissions, Action::UploadImg, &req, &info.into_inner()).await {
         return err;
     }
 
     // reject malformed requests
-    match form.image.size {
-        0 => return HttpResponse::BadRequest().body("Empty image"),
-        length if length > cfg.max_image_size_byte => {
+    if let Err(err) = check_size(form.image.size, &cfg) {
+        return err.to_response();
+    }
+
+    // Read image
+    let original = match read_img(&form.image) {
+        Ok(original) => original,
+        Err(err_msg) => return HttpResponse::BadRequest().body(err_msg),
+    };
+
+    let session_id = get_session_id(&req);
+    match process_upload(original, lobby_id, room_id, session_id, &cfg, &checker, &notify).await {
+        // Send image id back
+        Ok(uploaded) => HttpResponse::Ok()
+            .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+            .json(UploadResult::from(uploaded)),
+        Err(err) => err.to_response(),
+    }
+}
+
+/// Registered in main with its own multipart limit, `/upload-batch/{lobby_id}/{room_id}`
+pub async fn upload_img_batch(
+    info: web::Path<(LobbyId, RoomId)>,
+    form: MultipartForm<UploadBatchRequest>,
+    notify: Data<Addr<NotifyServer>>,
+    checker: Data<Addr<ImgChecker>>,
+    cfg: Data<ServerConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    let lobby_id = info.0;
+    let room_id = info.1;
+
+    // check permission once for all files
+    if let Some(err) = check(&cfg.permissions, Action::UploadImg, &req, &info.into_inner()).await {
+        return err;
+    }
+
+    let images = form.into_inner().images;
+    match images.len() {
+        0 => return HttpResponse::BadRequest().body("No images"),
+        count if count > cfg.max_batch_upload_files => {
             return HttpResponse::BadRequest().body(format!(
-                "The uploaded file is too large. Maximum size is {} bytes.",
-                cfg.max_image_size_byte
+                "Too many images. Maximum is {} per request.",
+                cfg.max_batch_upload_files
             ));
         }
         _ => {}
+    }
+
+    let session_id = get_session_id(&req);
+    let (cfg, checker, notify) = (&cfg, &checker, &notify);
+    let results: Vec<UploadBatchItemResult> = stream::iter(images)
+        .map(|image| async move {
+            let file_name = image.file_name.clone();
+            let result = match check_size(image.size, cfg) {
+                Ok(()) => match read_img(&image) {
+                    Ok(original) => {
+                        process_upload(original, lobby_id, room_id, session_id, cfg, checker, notify)
+                            .await
+                    }
+                    Err(err_msg) => Err(UploadError::BadRequest(err_msg.into())),
+                },
+                Err(err) => Err(err),
+            };
+            UploadBatchItemResult::new(file_name, result)
+        })
+        .buffered(cfg.batch_upload_parallelism.max(1))
+        .collect()
+        .await;
+
+    HttpResponse::Ok()
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .json(results)
+}
+
+#[post("/upload-url/{lobby_id}/{room_id}", wrap = "RateLimited(Action::UploadImg)")]
+pub async fn upload_img_from_url(
+    info: web::Path<(LobbyId, RoomId)>,
+    payload: Json<UploadUrlRequest>,
+    notify: Data<Addr<NotifyServer>>,
+    checker: Data<Addr<ImgChecker>>,
+    cfg: Data<ServerConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    let lobby_id = info.0;
+    let room_id = info.1;
+
+    // check permission
+    if let Some(err) = check(&cfg.permissions, Action::UploadImg, &req, &info.into_inner()).await {
+        return err;
+    }
+
+    // Fetch image
+    let original = match fetch_img(&payload.url, &cfg).await {
+        Ok(original) => original,
+        Err(err) => return err.to_response(),
     };
 
-    // Read image
-    let img = match read_img(&form.image) {
-        Ok(img) => img,
-        Err(err_msg) => return HttpResponse::BadRequest().body(err_msg),
+    let session_id = get_session_id(&req);
+    match process_upload(original, lobby_id, room_id, session_id, &cfg, &checker, &notify).await {
+        Ok(uploaded) => HttpResponse::Ok()
+            .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+            .json(UploadResult::from(uploaded)),
+        Err(err) => err.to_response(),
+    }
+}
+
+#[post("/upload-session/{lobby_id}/{room_id}", wrap = "RateLimited(Action::UploadImg)")]
+pub async fn create_upload_session(
+    info: web::Path<(LobbyId, RoomId)>,
+    payload: Json<UploadSessionRequest>,
+    cfg: Data<ServerConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    let lobby_id = info.0;
+    let room_id = info.1;
+
+    // check permission
+    if let Some(err) = check(&cfg.permissions, Action::UploadImg, &req, &info.into_inner()).await {
+        return err;
+    }
+
+    // reject malformed requests
+    let request = payload.into_inner();
+    if let Err(err) = check_size(request.size, &cfg) {
+        return err.to_response();
+    }
+    if ImageFormat::from_mime_type(&request.content_type).is_none() {
+        return HttpResponse::BadRequest().body("Unknown image format");
+    }
+
+    let session = UploadSession {
+        lobby_id,
+        room_id,
+        size: request.size,
+        content_type: request.content_type,
+        uploader_id: get_session_id(&req),
     };
+    match create_session(&cfg.chunked_upload, &session) {
+        Ok(upload_id) => HttpResponse::Ok().json(UploadSessionStatus {
+            upload_id,
+            offset: 0,
+            size: session.size,
+        }),
+        Err(err_msg) => HttpResponse::InternalServerError().body(err_msg),
+    }
+}
 
-    // Process image
-    let img = resize_image(img, 4000, 2000);
-    let thumb_img = resize_image(img.clone(), 600, 200);
-
-    // At upload check
-    if let Some(check) = &cfg.upload_check {
-        if check.check_phase == CheckPhase::BeforeUpload {
-            match check_image(&check.url, thumb_img.clone(), None).await {
-                Ok(is_allowed) => {
-                    if !is_allowed {
-                        return HttpResponse::Forbidden().body(
-                            check
-                                .not_allowed_msg
-                                .as_ref()
-                                .map_or("This image is not allowed".into(), |msg| msg.clone()),
-                        );
-                    }
-                }
-                Err(err_msg) => {
-                    return HttpResponse::InternalServerError()
-                        .body(format!("Upload check failed: {err_msg}"))
-                }
-            }
-        }
+#[get("/upload-session/{upload_id}", wrap = "RateLimited(Action::UploadImg)")]
+pub async fn get_upload_session(
+    path: web::Path<(UploadId,)>,
+    cfg: Data<ServerConfig>,
+) -> impl Responder {
+    let upload_id = path.0;
+    match read_session(&cfg.chunked_upload, &upload_id) {
+        Some((session, offset)) => HttpResponse::Ok().json(UploadSessionStatus {
+            upload_id,
+            offset,
+            size: session.size,
+        }),
+        None => HttpResponse::NotFound().body("Upload not found"),
     }
+}
 
-    // Save images
-    let img_id = match save_img(
-        &img,
-        &thumb_img,
-        &lobby_id,
-        &room_id,
-        &cfg.images_storage_path,
-    ) {
-        SaveImageResult::Ok(id) => id,
-        SaveImageResult::ImageAlreadyExists(img_id) => {
-            return HttpResponse::Ok()
-                .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
-                .json(UploadResult { img_id })
+#[post("/upload-chunk/{upload_id}/{offset}", wrap = "RateLimited(Action::UploadImg)")]
+pub async fn upload_chunk(
+    path: web::Path<(UploadId, usize)>,
+    payload: web::Payload,
+    cfg: Data<ServerConfig>,
+) -> impl Responder {
+    let (upload_id, offset) = path.into_inner();
+    match append_chunk(&cfg.chunked_upload, &upload_id, offset, payload).await {
+        Ok(offset) => HttpResponse::Ok().json(UploadSessionStatus {
+            upload_id,
+            offset,
+            size: read_session(&cfg.chunked_upload, &upload_id).map_or(offset, |(s, _)| s.size),
+        }),
+        Err(ChunkError::NotFound) => HttpResponse::NotFound().body("Upload not found"),
+        Err(ChunkError::OffsetMismatch(current)) => HttpResponse::Conflict()
+            .body(format!("Wrong offset {offset}, upload continues at {current}")),
+        Err(ChunkError::TooLarge) => {
+            HttpResponse::BadRequest().body("Chunk exceeds announced upload size")
+        }
+        Err(ChunkError::Busy) => {
+            HttpResponse::Conflict().body("Another chunk of this upload is in progress")
         }
-        SaveImageResult::Err(err_msg) => return HttpResponse::InternalServerError().body(err_msg),
+        Err(ChunkError::Io(err_msg)) => HttpResponse::InternalServerError().body(err_msg),
+    }
+}
+
+#[post("/upload-finalize/{upload_id}")]
+pub async fn finalize_upload(
+    path: web::Path<(UploadId,)>,
+    notify: Data<Addr<NotifyServer>>,
+    checker: Data<Addr<ImgChecker>>,
+    cfg: Data<ServerConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    let upload_id = path.0;
+    let Some(_lock) = UploadLock::acquire(upload_id) else {
+        return HttpResponse::Conflict().body("A chunk of this upload is in progress");
+    };
+    let Some((session, offset)) = read_session(&cfg.chunked_upload, &upload_id) else {
+        return HttpResponse::NotFound().body("Upload not found");
     };
+    let (lobby_id, room_id) = (session.lobby_id, session.room_id);
 
-    // After upload check
-    if let Some(check) = &cfg.upload_check {
-        if check.check_phase == CheckPhase::AfterUpload {
-            checker.do_send(ImgCheck::new(
-                thumb_img,
-                lobby_id,
-                room_id,
-                img_id,
-                get_session_id(&req),
-            ));
-        }
+    // check permission
+    if let Some(err) =
+        check(&cfg.permissions, Action::UploadImg, &req, &(lobby_id, room_id)).await
+    {
+        return err;
     }
 
-    // Notify users
-    notify
-        .send(ImageUploaded::new(lobby_id, room_id, img_id))
-        .await
-        .unwrap_or_else(|err| warn!("Can't notify users: {}", err));
+    if offset != session.size {
+        return HttpResponse::BadRequest()
+            .body(format!("Upload incomplete: {offset} of {} bytes", session.size));
+    }
 
-    // Send image id back
-    HttpResponse::Ok()
-        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
-        .json(UploadResult { img_id })
+    // Read image
+    let part_path = part_path(&cfg.chunked_upload, &upload_id);
+    let original = read_img_file(&part_path, &session.content_type);
+    remove_session(&cfg.chunked_upload, &upload_id);
+    let original = match original {
+        Ok(original) => original,
+        Err(err_msg) => return HttpResponse::BadRequest().body(err_msg),
+    };
+
+    let uploader_id = session.uploader_id.or_else(|| get_session_id(&req));
+    match process_upload(original, lobby_id, room_id, uploader_id, &cfg, &checker, &notify).await {
+        Ok(uploaded) => HttpResponse::Ok()
+            .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+            .json(UploadResult::from(uploaded)),
+        Err(err) => err.to_response(),
+    }
 }
 
-#[post("/delete/{lobby_id}")]
+#[post("/delete/{lobby_id}", wrap = "RateLimited(Action::DeleteLobby)")]
 pub async fn delete_lobby(
     path: web::Path<(LobbyId,)>,
     notify: Data<Addr<NotifyServer>>,
@@ -217,7 +404,9 @@ pub async fn delete_lobby(
     let lobby_id = path.0;
 
     // check permission
-    if let Some(err) = check(&cfg.permissions.delete_lobby, &req, &path.into_inner()).await {
+    if let Some(err) =
+        check(&cfg.permissions, Action::DeleteLobby, &req, &path.into_inner()).await
+    {
         return err;
     }
 
@@ -238,7 +427,7 @@ pub async fn delete_lobby(
     HttpResponse::Ok().json(Success)
 }
 
-#[post("/delete/{lobby_id}/{room_id}")]
+#[post("/delete/{lobby_id}/{room_id}", wrap = "RateLimited(Action::DeleteRoom)")]
 pub async fn delete_room(
     path: web::Path<(LobbyId, RoomId)>,
     notify: Data<Addr<NotifyServer>>,
@@ -249,7 +438,7 @@ pub async fn delete_room(
     let room_id = path.1;
 
     // check permission
-    if let Some(err) = check(&cfg.permissions.delete_room, &req, &path.into_inner()).await {
+    if let Some(err) = check(&cfg.permissions, Action::DeleteRoom, &req, &path.into_inner()).await {
         return err;
     }
 
@@ -272,7 +461,7 @@ pub async fn delete_room(
     HttpResponse::Ok().json(Success)
 }
 
-#[post("/delete/{lobby_id}/{room_id}/{file}")]
+#[post("/delete/{lobby_id}/{room_id}/{file}", wrap = "RateLimited(Action::DeleteImg)")]
 pub async fn delete_img(
     path: web::Path<(LobbyId, RoomId, ImgId)>,
     notify: Data<Addr<NotifyServer>>,
@@ -284,11 +473,12 @@ pub async fn delete_img(
     let img_id = path.2;
 
     // check permission
-    if let Some(err) = check(&cfg.permissions.delete_img, &req, &path.into_inner()).await {
+    if let Some(err) = check(&cfg.permissions, Action::DeleteImg, &req, &path.into_inner()).await {
         return err;
     }
 
-    delete_img_files((lobby_id, room_id, img_id), &cfg.images_storage_path);
+    delete_img_files((lobby_id, room_id, img_id), &cfg.images_storage_path)
+        .unwrap_or_else(|err| debug!("Can't delete img {img_id}: {err}"));
 
     // Notify users
     notify
@@ -299,6 +489,95 @@ pub async fn delete_img(
     HttpResponse::Ok().json(Success)
 }
 
+#[post("/delete-batch/{lobby_id}", wrap = "RateLimited(Action::DeleteImg)")]
+pub async fn delete_img_batch(
+    path: web::Path<(LobbyId,)>,
+    payload: Json<DeleteBatchRequest>,
+    notify: Data<Addr<NotifyServer>>,
+    cfg: Data<ServerConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    delete_imgs(path.0, payload.into_inner().images, &notify, &cfg, &req).await
+}
+
+#[post("/delete-batch/{lobby_id}/{room_id}", wrap = "RateLimited(Action::DeleteImg)")]
+pub async fn delete_room_img_batch(
+    path: web::Path<(LobbyId, RoomId)>,
+    payload: Json<DeleteRoomBatchRequest>,
+    notify: Data<Addr<NotifyServer>>,
+    cfg: Data<ServerConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    let lobby_id = path.0;
+    let room_id = path.1;
+    let images = payload
+        .into_inner()
+        .img_ids
+        .into_iter()
+        .map(|img_id| ImgRef { room_id, img_id })
+        .collect();
+    delete_imgs(lobby_id, images, &notify, &cfg, &req).await
+}
+
+async fn delete_imgs(
+    lobby_id: LobbyId,
+    images: Vec<ImgRef>,
+    notify: &Addr<NotifyServer>,
+    cfg: &ServerConfig,
+    req: &HttpRequest,
+) -> HttpResponse {
+    match images.len() {
+        0 => return HttpResponse::BadRequest().body("No images"),
+        count if count > cfg.max_batch_delete_images => {
+            return HttpResponse::BadRequest().body(format!(
+                "Too many images. Maximum is {} per request.",
+                cfg.max_batch_delete_images
+            ));
+        }
+        _ => {}
+    }
+
+    // check permission once per room, one after another
+    let mut permissions: HashMap<RoomId, Result<(), String>> = HashMap::new();
+    for img in &images {
+        if let Entry::Vacant(entry) = permissions.entry(img.room_id) {
+            let scope = (lobby_id, img.room_id);
+            let permission = authorize(&cfg.permissions, Action::DeleteImg, req, &scope)
+                .await
+                .map_err(|denied| denied.to_string());
+            entry.insert(permission);
+        }
+    }
+
+    let results: Vec<DeleteBatchItemResult> = images
+        .into_iter()
+        .map(|img| {
+            let result = permissions[&img.room_id].clone().and_then(|()| {
+                delete_img_files((lobby_id, img.room_id, img.img_id), &cfg.images_storage_path)
+            });
+            DeleteBatchItemResult::new(img, result)
+        })
+        .collect();
+
+    // Notify users once for all deleted images
+    let deleted: Vec<ImgRef> = results
+        .iter()
+        .filter(|res| res.deleted)
+        .map(|res| ImgRef {
+            room_id: res.room_id,
+            img_id: res.img_id,
+        })
+        .collect();
+    if !deleted.is_empty() {
+        notify
+            .send(ImagesDeleted::new(lobby_id, deleted))
+            .await
+            .unwrap_or_else(|err| warn!("Can't notify users: {}", err));
+    }
+
+    HttpResponse::Ok().json(results)
+}
+
 #[post("/chat")]
 pub async fn send_chat_message(
     payload: Json<ChatMessageRequest>,
@@ -311,7 +590,7 @@ pub async fn send_chat_message(
     let msg = request.msg;
 
     // check permission
-    if let Some(err) = check(&cfg.permissions.send_chat_message, &req, &(lobby_id,)).await {
+    if let Some(err) = check(&cfg.permissions, Action::SendChatMessage, &req, &(lobby_id,)).await {
         return err;
     }
 
@@ -324,6 +603,29 @@ pub async fn send_chat_message(
     HttpResponse::Ok().json(Success)
 }
 
+#[get("/presence/{lobby_id}", wrap = "RateLimited(Action::GetPresence)")]
+pub async fn get_presence(
+    info: web::Path<(LobbyId,)>,
+    notify: Data<Addr<NotifyServer>>,
+    cfg: Data<ServerConfig>,
+    req: HttpRequest,
+) -> impl Responder {
+    let lobby_id = info.0;
+
+    // check permission
+    if let Some(err) = check(&cfg.permissions, Action::GetPresence, &req, &(lobby_id,)).await {
+        return err;
+    }
+
+    match notify.send(GetPresence::new(lobby_id)).await {
+        Ok(members) => HttpResponse::Ok().json(members),
+        Err(err) => {
+            warn!("Can't get presence: {}", err);
+            HttpResponse::ServiceUnavailable().body("Notifications unavailable")
+        }
+    }
+}
+
 #[get("/")]
 pub async fn test() -> impl Responder {
     debug!("Test ping");
@@ -5,6 +5,7 @@ use crate::{
         internal_messages::{ImageDeleted, SystemNotification, SystemNotificationType},
         server::NotifyServer,
     },
+    utils::http_client,
     ImgId, LobbyId, RoomId, SessionId,
 };
 use actix::prelude::*;
@@ -79,7 +80,8 @@ impl Handler<ImgCheck> for ImgChecker {
                     delete_img_files(
                         (msg.lobby_id, msg.room_id, msg.img_id),
                         &cfg.images_storage_path,
-                    );
+                    )
+                    .unwrap_or_else(|err| warn!("Can't delete img {}: {err}", msg.img_id));
                     notify
                         .send(ImageDeleted::new(msg.lobby_id, msg.room_id, msg.img_id))
                         .await
@@ -124,7 +126,7 @@ pub async fn check_image(
     let form = reqwest::multipart::Form::new()
         .text("img_id", img_id.to_string())
         .part("image", part);
-    let res = reqwest::Client::new()
+    let res = http_client()
         .post(url)
         .header("CONTENT_TYPE", "Multipart/form-data")
         .header("CONTENT_LENGTH", content_len)
@@ -132,5 +134,5 @@ pub async fn check_image(
         .send()
         .await
         .map_err(|err| format!("{err:?}"))?;
-    Ok(res.json::<bool>().await.map_err(|err| format!("{err:?}"))?)
+    res.json::<bool>().await.map_err(|err| format!("{err:?}"))
 }
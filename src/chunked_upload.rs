@@ -0,0 +1,207 @@
+use crate::{
+    LobbyId, RoomId, SessionId,
+    config::{ChunkedUploadCfg, ServerConfig},
+};
+use actix::prelude::*;
+use actix_web::web::{Data, Payload};
+use futures_util::StreamExt as _;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt as _};
+use uuid::Uuid;
+
+pub type UploadId = Uuid;
+
+const META_EXTENSION: &str = "json";
+const PART_EXTENSION: &str = "part";
+
+// uploads with a request writing to them right now
+static BUSY_UPLOADS: LazyLock<Mutex<HashSet<UploadId>>> = LazyLock::new(Default::default);
+
+/// Everything needed to continue an upload, stored next to the received bytes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadSession {
+    pub lobby_id: LobbyId,
+    pub room_id: RoomId,
+    pub size: usize,
+    pub content_type: String,
+    pub uploader_id: Option<SessionId>,
+}
+
+pub enum ChunkError {
+    NotFound,
+    OffsetMismatch(usize),
+    TooLarge,
+    Busy,
+    Io(String),
+}
+
+/// Marks an upload as busy until dropped, so only one request at a time writes to it
+pub struct UploadLock(UploadId);
+
+impl UploadLock {
+    /// `None` while another request holds the lock of this upload
+    pub fn acquire(upload_id: UploadId) -> Option<Self> {
+        let mut busy = BUSY_UPLOADS.lock().unwrap_or_else(|err| err.into_inner());
+        busy.insert(upload_id).then(|| Self(upload_id))
+    }
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        let mut busy = BUSY_UPLOADS.lock().unwrap_or_else(|err| err.into_inner());
+        busy.remove(&self.0);
+    }
+}
+
+fn meta_path(cfg: &ChunkedUploadCfg, upload_id: &UploadId) -> PathBuf {
+    Path::new(&cfg.temp_path).join(format!("{upload_id}.{META_EXTENSION}"))
+}
+
+pub fn part_path(cfg: &ChunkedUploadCfg, upload_id: &UploadId) -> PathBuf {
+    Path::new(&cfg.temp_path).join(format!("{upload_id}.{PART_EXTENSION}"))
+}
+
+pub fn create_session(cfg: &ChunkedUploadCfg, session: &UploadSession) -> Result<UploadId, String> {
+    fs::create_dir_all(&cfg.temp_path)
+        .map_err(|err| format!("Can't create chunk folder: {} - {err}", cfg.temp_path))?;
+    let upload_id = Uuid::new_v4();
+    let meta = serde_json::to_string(session).map_err(|err| err.to_string())?;
+    fs::write(meta_path(cfg, &upload_id), meta).map_err(|err| err.to_string())?;
+    File::create(part_path(cfg, &upload_id)).map_err(|err| err.to_string())?;
+    Ok(upload_id)
+}
+
+pub fn read_session(
+    cfg: &ChunkedUploadCfg,
+    upload_id: &UploadId,
+) -> Option<(UploadSession, usize)> {
+    let meta = fs::read_to_string(meta_path(cfg, upload_id)).ok()?;
+    let session = serde_json::from_str(&meta).ok()?;
+    let offset = fs::metadata(part_path(cfg, upload_id)).ok()?.len() as usize;
+    Some((session, offset))
+}
+
+pub fn remove_session(cfg: &ChunkedUploadCfg, upload_id: &UploadId) {
+    fs::remove_file(part_path(cfg, upload_id)).unwrap_or_default();
+    fs::remove_file(meta_path(cfg, upload_id)).unwrap_or_default();
+}
+
+/// Appends the request body at `offset` and returns the new offset. Bytes of a failed chunk are
+/// cut off again, so the client can simply retry from the last confirmed offset. Fails with
+/// `Busy` while another chunk of the upload is written.
+pub async fn append_chunk(
+    cfg: &ChunkedUploadCfg,
+    upload_id: &UploadId,
+    offset: usize,
+    mut payload: Payload,
+) -> Result<usize, ChunkError> {
+    let _lock = UploadLock::acquire(*upload_id).ok_or(ChunkError::Busy)?;
+    let (session, current_offset) = read_session(cfg, upload_id).ok_or(ChunkError::NotFound)?;
+    if offset != current_offset {
+        return Err(ChunkError::OffsetMismatch(current_offset));
+    }
+
+    let path = part_path(cfg, upload_id);
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .await
+        .map_err(|err| ChunkError::Io(err.to_string()))?;
+
+    let mut new_offset = offset;
+    let res = loop {
+        let Some(chunk) = payload.next().await else {
+            break Ok(new_offset);
+        };
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => break Err(ChunkError::Io(err.to_string())),
+        };
+        if new_offset + chunk.len() > session.size {
+            break Err(ChunkError::TooLarge);
+        }
+        if let Err(err) = file.write_all(&chunk).await {
+            break Err(ChunkError::Io(err.to_string()));
+        }
+        new_offset += chunk.len();
+    };
+
+    let res = match res {
+        Ok(new_offset) => file
+            .flush()
+            .await
+            .map(|()| new_offset)
+            .map_err(|err| ChunkError::Io(err.to_string())),
+        Err(err) => Err(err),
+    };
+    if res.is_err() {
+        file.set_len(offset as u64).await.unwrap_or_else(|err| {
+            warn!("Can't reset upload {upload_id} to offset {offset}: {err}")
+        });
+    }
+    res
+}
+
+pub struct ChunkCleaner {
+    cfg: Data<ServerConfig>,
+}
+
+impl ChunkCleaner {
+    pub fn new(cfg: Data<ServerConfig>) -> Self {
+        Self { cfg }
+    }
+
+    fn remove_expired(&self) {
+        let cfg = &self.cfg.chunked_upload;
+        let Ok(entries) = fs::read_dir(&cfg.temp_path) else {
+            return;
+        };
+        let expire_after = Duration::from_secs(cfg.expire_after_sec);
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(upload_id) = path
+                .file_stem()
+                .and_then(|stem| UploadId::parse_str(&stem.to_string_lossy()).ok())
+            else {
+                continue;
+            };
+            // uploads are aged by their received bytes, a session file without them is left
+            // over by an interrupted create or remove and aged by itself
+            let is_part = path.extension().is_some_and(|ext| ext == PART_EXTENSION);
+            let is_orphan = path.extension().is_some_and(|ext| ext == META_EXTENSION)
+                && !part_path(cfg, &upload_id).exists();
+            if !is_part && !is_orphan {
+                continue;
+            }
+            let is_expired = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age > expire_after);
+            // an upload with a chunk in progress is still in use
+            let lock = is_expired.then(|| UploadLock::acquire(upload_id)).flatten();
+            if lock.is_some() {
+                debug!("Remove abandoned upload {upload_id}");
+                remove_session(cfg, &upload_id);
+            }
+        }
+    }
+}
+
+impl Actor for ChunkCleaner {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let interval = Duration::from_secs(self.cfg.chunked_upload.cleanup_interval_sec);
+        ctx.run_interval(interval, |cleaner, _| cleaner.remove_expired());
+    }
+}
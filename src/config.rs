@@ -1,6 +1,13 @@
-use crate::permission::Permissions;
+use crate::{
+    LobbyId,
+    permission::{
+        Permissions, audit::AuditLogCfg, confirmation::ConfirmationRequest, ip::IpNetwork,
+    },
+};
 use actix_cors::Cors;
 use actix_web::http::header;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use log::warn;
 use serde::Deserialize;
 use std::{env::current_dir, fs};
 
@@ -18,9 +25,29 @@ pub struct ServerConfig {
     // maximum input image file size
     pub max_image_size_byte: usize,
 
+    // maximum number of files in one batch upload request
+    #[serde(default = "default_max_batch_upload_files")]
+    pub max_batch_upload_files: usize,
+
+    // maximum number of images in one batch delete request
+    #[serde(default = "default_max_batch_delete_images")]
+    pub max_batch_delete_images: usize,
+
+    // number of files of one batch upload processed at the same time
+    #[serde(default = "default_batch_upload_parallelism")]
+    pub batch_upload_parallelism: usize,
+
+    // keep the uploaded files next to the converted images
+    #[serde(default)]
+    pub keep_originals: KeepOriginals,
+
     // upload permission
     pub permissions: Permissions,
 
+    // reverse proxies allowed to set the client address via Forwarded/X-Forwarded-For
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNetwork>,
+
     // Certificate path for openssl
     #[cfg(feature = "openssl")]
     pub cert_pem_path: Option<String>,
@@ -30,6 +57,18 @@ pub struct ServerConfig {
     pub key_pem_path: Option<String>,
 
     pub upload_check: Option<UploadCheckCfg>,
+
+    // resumable chunked uploads
+    #[serde(default)]
+    pub chunked_upload: ChunkedUploadCfg,
+
+    // uploads fetched by the server from an url
+    #[serde(default)]
+    pub url_upload: UrlUploadCfg,
+
+    // live notifications over websocket
+    #[serde(default)]
+    pub notifications: NotificationCfg,
 }
 
 impl Default for ServerConfig {
@@ -39,7 +78,12 @@ impl Default for ServerConfig {
             port: 1871,
             images_storage_path: String::from("/wim_storage/pictures"),
             max_image_size_byte: 1024 * 1024 * 20, // 20 MB
+            max_batch_upload_files: default_max_batch_upload_files(),
+            max_batch_delete_images: default_max_batch_delete_images(),
+            batch_upload_parallelism: default_batch_upload_parallelism(),
+            keep_originals: KeepOriginals::default(),
             permissions: Permissions::default(),
+            trusted_proxies: Vec::new(),
 
             #[cfg(feature = "openssl")]
             cert_pem_path: None, // Example: Some(String::from("/wim_storage/cert/cert.pem")),
@@ -48,6 +92,31 @@ impl Default for ServerConfig {
             key_pem_path: None, // Example: Some(String::from("/wim_storage/cert/key.pem")),
 
             upload_check: None,
+            chunked_upload: ChunkedUploadCfg::default(),
+            url_upload: UrlUploadCfg::default(),
+            notifications: NotificationCfg::default(),
+        }
+    }
+}
+
+fn default_max_batch_upload_files() -> usize {
+    50
+}
+
+fn default_max_batch_delete_images() -> usize {
+    100
+}
+
+fn default_batch_upload_parallelism() -> usize {
+    4
+}
+
+impl ServerConfig {
+    pub fn keeps_originals(&self, lobby_id: &LobbyId) -> bool {
+        match &self.keep_originals {
+            KeepOriginals::Never => false,
+            KeepOriginals::Always => true,
+            KeepOriginals::Lobbies(lobbies) => lobbies.contains(lobby_id),
         }
     }
 }
@@ -69,23 +138,33 @@ pub fn read_server_config() -> Result<ServerConfig, String> {
             }
         },
     };
-    let cfg = match serde_json::from_str(&cfg_json) {
+    let mut cfg: ServerConfig = match serde_json::from_str(&cfg_json) {
         Ok(cfg) => cfg,
         Err(err) => return Err(format!("Invalid config json: {err}")),
     };
+    cfg.notifications.init_session_secret()?;
     Ok(cfg)
 }
 
-pub fn cors_cfg() -> Cors {
-    Cors::default()
+/// Allows the origins of `permissions.allowed_origins`, or any origin if not configured
+pub fn cors_cfg(cfg: &ServerConfig) -> Cors {
+    let cors = match cfg.permissions.allowed_origins.clone() {
+        Some(origins) => Cors::default().allowed_origin_fn(move |origin, _| {
+            let origin = origin.to_str().unwrap_or_default();
+            origins.iter().any(|pattern| pattern.matches_header(origin))
+        }),
+        None => Cors::default().allow_any_origin(),
+    };
+    cors
         .allowed_methods(vec!["GET", "POST"])
         .allowed_headers(vec![
             header::AUTHORIZATION,
             header::ACCEPT,
             header::CONTENT_TYPE,
             header::CONTENT_LENGTH,
+            header::HeaderName::from_static("x-owner-token"),
+            header::HeaderName::from_static("last-event-id"),
         ])
-        .allow_any_origin()
         .supports_credentials()
         .max_age(3600)
 }
@@ -102,3 +181,217 @@ pub struct UploadCheckCfg {
     pub url: String,
     pub not_allowed_msg: Option<String>,
 }
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ChunkedUploadCfg {
+    // Path for storing unfinished uploads
+    pub temp_path: String,
+
+    // unfinished uploads without new chunks are removed after this time
+    pub expire_after_sec: u64,
+
+    // how often abandoned uploads are searched
+    pub cleanup_interval_sec: u64,
+}
+
+impl Default for ChunkedUploadCfg {
+    fn default() -> Self {
+        Self {
+            temp_path: String::from("./wim-storage/chunks"),
+            expire_after_sec: 60 * 60 * 24, // 1 day
+            cleanup_interval_sec: 60 * 10,  // 10 min
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct UrlUploadCfg {
+    // maximum time for fetching one image
+    pub timeout_sec: u64,
+
+    // maximum number of followed redirects
+    pub max_redirects: usize,
+}
+
+impl Default for UrlUploadCfg {
+    fn default() -> Self {
+        Self {
+            timeout_sec: 10,
+            max_redirects: 3,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct NotificationCfg {
+    // events kept per lobby for clients reconnecting with `?since=`
+    pub replay_buffer_size: usize,
+
+    // key for signing session ids, required with a shared broker, a random one is used if not set
+    #[serde(default)]
+    pub session_secret: String,
+
+    // disconnected sessions can be resumed for this time
+    pub session_grace_period_sec: u64,
+
+    // session tokens and cookies are accepted for this time after they were issued
+    pub session_token_ttl_sec: u64,
+
+    // asked on connect for the display name of the client, `?name=` is ignored then
+    pub identity: Option<ConfirmationRequest>,
+
+    // messages waiting to be sent over one connection
+    pub session_queue_size: usize,
+
+    // what happens to messages for a connection with a full queue
+    pub slow_consumer_policy: SlowConsumerPolicy,
+
+    // how notifications reach the clients connected to other server instances
+    pub broker: BrokerCfg,
+
+    // backends the lobby events are posted to
+    pub webhooks: WebhooksCfg,
+}
+
+impl Default for NotificationCfg {
+    fn default() -> Self {
+        Self {
+            replay_buffer_size: 100,
+            session_secret: URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()),
+            session_grace_period_sec: 60,
+            session_token_ttl_sec: 86400,
+            identity: None,
+            session_queue_size: 256,
+            slow_consumer_policy: SlowConsumerPolicy::default(),
+            broker: BrokerCfg::default(),
+            webhooks: WebhooksCfg::default(),
+        }
+    }
+}
+
+impl NotificationCfg {
+    /// Every instance behind a shared broker has to accept the session tokens of the others
+    fn init_session_secret(&mut self) -> Result<(), String> {
+        if !self.session_secret.is_empty() {
+            return Ok(());
+        }
+        if !matches!(self.broker, BrokerCfg::InProcess) {
+            return Err(String::from(
+                "notifications.session_secret is required with a shared broker",
+            ));
+        }
+        warn!("notifications.session_secret not set, sessions can't be resumed after a restart");
+        self.session_secret = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    // the oldest queued message is dropped, the client notices the gap in `seq`
+    #[default]
+    DropOldest,
+
+    // the connection is closed, the client can reconnect with `since` and `session`
+    Disconnect,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub enum BrokerCfg {
+    // single instance, notifications stay inside this process
+    #[default]
+    InProcess,
+
+    // every instance publishes to and subscribes on a Redis pub/sub channel
+    Redis(RedisCfg),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RedisCfg {
+    // redis://[user:password@]host[:port]
+    pub url: String,
+
+    // pub/sub channel shared by all instances
+    #[serde(default = "default_redis_channel")]
+    pub channel: String,
+
+    // events waiting to be published, newer events are dropped while Redis is unreachable
+    #[serde(default = "default_redis_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_redis_channel() -> String {
+    String::from("wim-notifications")
+}
+
+fn default_redis_queue_size() -> usize {
+    1024
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct WebhooksCfg {
+    // endpoints the lobby events are posted to
+    pub subscriptions: Vec<WebhookSubscription>,
+
+    // failed deliveries waiting for their retry, kept over restarts if set
+    pub queue_path: Option<String>,
+
+    // every delivery attempt is appended to this log
+    pub delivery_log: Option<AuditLogCfg>,
+
+    // deliveries kept for retries, newer ones are dropped when the queue is full
+    pub max_queue_size: usize,
+
+    // a delivery is given up after this many failed attempts
+    pub max_attempts: u32,
+
+    // wait before the first retry, doubled for every further one up to the max
+    pub initial_backoff_sec: u64,
+    pub max_backoff_sec: u64,
+
+    // time a webhook has to answer
+    pub timeout_sec: u64,
+
+    // deliveries posted at the same time, the others wait for a free slot
+    pub max_concurrency: usize,
+}
+
+impl Default for WebhooksCfg {
+    fn default() -> Self {
+        Self {
+            subscriptions: Vec::new(),
+            queue_path: None,
+            delivery_log: None,
+            max_queue_size: 10000,
+            max_attempts: 10,
+            initial_backoff_sec: 5,
+            max_backoff_sec: 60 * 60,
+            timeout_sec: 10,
+            max_concurrency: 16,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WebhookSubscription {
+    pub url: String,
+
+    // key of the HMAC-SHA256 signature sent in the `X-Wim-Signature` header
+    pub secret: String,
+
+    // lobbies whose events are posted, events of all lobbies if not set
+    #[serde(default)]
+    pub lobbies: Option<Vec<LobbyId>>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub enum KeepOriginals {
+    #[default]
+    Never,
+    Always,
+    Lobbies(Vec<LobbyId>),
+}
@@ -1,4 +1,4 @@
-use crate::{ImgId, LobbyId, RoomId};
+use crate::{ImgId, LobbyId, RoomId, permission::owner::owner_path};
 use actix_multipart::form::tempfile::TempFile;
 use actix_web::{HttpResponse, http::header};
 use image::{DynamicImage, GenericImageView, ImageFormat, imageops::FilterType};
@@ -7,7 +7,7 @@ use log::info;
 use std::{
     cmp::Reverse,
     fs::{self, DirEntry, File, create_dir_all},
-    io::{BufReader, Error, Read},
+    io::{Error, ErrorKind, Read},
     path::{Path, PathBuf},
     time::SystemTime,
 };
@@ -18,6 +18,27 @@ const IMG_EXTENSION: &str = "webp";
 pub enum ImgType {
     Big,
     Thumb,
+    Original,
+}
+
+/// Uploaded file as it was sent by the user
+pub struct OriginalImg {
+    pub bytes: Vec<u8>,
+    pub format: ImageFormat,
+}
+
+impl OriginalImg {
+    pub fn new(bytes: Vec<u8>, format: ImageFormat) -> Self {
+        Self { bytes, format }
+    }
+
+    pub fn decode(&self) -> Result<DynamicImage, &'static str> {
+        image::load_from_memory_with_format(&self.bytes, self.format).map_err(|_| "Image corrupt")
+    }
+
+    fn extension(&self) -> &'static str {
+        self.format.extensions_str().first().copied().unwrap_or("bin")
+    }
 }
 
 pub fn get_img(
@@ -30,13 +51,18 @@ pub fn get_img(
     let img_id = params.2;
 
     let mut file_base_path = Path::new(img_storage_path).join(lobby_id).join(room_id);
-    if img_type == ImgType::Thumb {
-        file_base_path = file_base_path.join("thumb");
+    match img_type {
+        ImgType::Big => {}
+        ImgType::Thumb => file_base_path = file_base_path.join("thumb"),
+        ImgType::Original => file_base_path = file_base_path.join("original"),
     }
-    file_base_path = file_base_path.join(img_id.to_string());
 
     // Open file
-    let Ok((mut file, filepath)) = open_img(&file_base_path) else {
+    let opened = match img_type {
+        ImgType::Original => open_original(&file_base_path, img_id),
+        _ => open_img(file_base_path.join(img_id.to_string())),
+    };
+    let Ok((mut file, filepath)) = opened else {
         return HttpResponse::NotFound().body("Picture not found");
     };
 
@@ -47,26 +73,34 @@ pub fn get_img(
     };
 
     // Send file back
-    HttpResponse::Ok()
-        .append_header(header::ContentDisposition::attachment(
-            filepath.to_string_lossy().to_string(),
-        ))
-        .body(img_content)
+    let mut res = HttpResponse::Ok();
+    res.append_header(header::ContentDisposition::attachment(
+        filepath.to_string_lossy().to_string(),
+    ));
+    if let Some(format) = filepath
+        .extension()
+        .and_then(ImageFormat::from_extension)
+    {
+        res.content_type(format.to_mime_type());
+    }
+    res.body(img_content)
 }
 
-pub fn read_img(temp_file: &TempFile) -> Result<DynamicImage, &'static str> {
-    let Ok(file) = std::fs::File::open(&temp_file.file) else {
-        return Err("Cannot read file");
-    };
-    let reader = BufReader::new(file);
+pub fn read_img(temp_file: &TempFile) -> Result<OriginalImg, &'static str> {
     let format = temp_file
         .content_type
         .as_ref()
         .ok_or("Can't read image format")?;
-    let format = ImageFormat::from_mime_type(format).ok_or("Unknown image format")?;
-    let img = image::load(reader, format).map_err(|_| "Image corrupt")?;
+    read_img_file(temp_file.file.path(), format.essence_str())
+}
 
-    Ok(img)
+pub fn read_img_file(path: &Path, mime_type: &str) -> Result<OriginalImg, &'static str> {
+    let format = ImageFormat::from_mime_type(mime_type).ok_or("Unknown image format")?;
+    let Ok(bytes) = fs::read(path) else {
+        return Err("Cannot read file");
+    };
+
+    Ok(OriginalImg::new(bytes, format))
 }
 
 pub fn resize_image(img: DynamicImage, max_width: u32, max_height: u32) -> DynamicImage {
@@ -88,19 +122,19 @@ pub enum SaveImageResult {
 pub fn save_img(
     img: &DynamicImage,
     thumb_img: &DynamicImage,
+    original: Option<&OriginalImg>,
     lobby_id: &LobbyId,
     room_id: &RoomId,
     img_storage_path: &str,
 ) -> SaveImageResult {
     // Check storage path
     let storage_path = Path::new(img_storage_path);
-    if !storage_path.exists() {
-        if let Err(err) = create_dir_all(img_storage_path) {
-            return SaveImageResult::Err(format!(
-                "Can't create storage folder: {img_storage_path} - {}",
-                err.to_string()
-            ));
-        }
+    if !storage_path.exists()
+        && let Err(err) = create_dir_all(img_storage_path)
+    {
+        return SaveImageResult::Err(format!(
+            "Can't create storage folder: {img_storage_path} - {err}"
+        ));
     }
 
     // Check image folder
@@ -141,6 +175,19 @@ pub fn save_img(
         return SaveImageResult::Err(err);
     }
 
+    // Save original file
+    if let Some(original) = original {
+        let original_folder_path = img_folder_path.join("original");
+        if !original_folder_path.exists() && create_dir_all(&original_folder_path).is_err() {
+            return SaveImageResult::Err(String::from("Could not create original folder"));
+        }
+        let original_path =
+            original_folder_path.join(format!("{img_id}.{}", original.extension()));
+        if let Err(err) = fs::write(original_path, &original.bytes) {
+            return SaveImageResult::Err(format!("Could not save original: {err}"));
+        }
+    }
+
     SaveImageResult::Ok(img_id)
 }
 
@@ -185,19 +232,33 @@ pub fn get_filenames_as_img_id(folder_path: &PathBuf) -> std::io::Result<Vec<Img
     Ok(entries.into_iter().map(|(id, _)| id).collect())
 }
 
-pub fn delete_img_files(params: (LobbyId, RoomId, ImgId), images_storage_path: &str) {
+pub fn delete_img_files(
+    params: (LobbyId, RoomId, ImgId),
+    images_storage_path: &str,
+) -> Result<(), String> {
     let room_path = Path::new(images_storage_path)
         .join(params.0.to_string())
         .join(params.1.to_string());
     let filename = img_id_to_filename(params.2);
 
-    // Delete big image
-    let img_path = room_path.join(&filename);
-    fs::remove_file(img_path).unwrap_or_default();
-
     // Delete thumb image
-    let thumb_path = room_path.join("thumb").join(filename);
+    let thumb_path = room_path.join("thumb").join(&filename);
     fs::remove_file(thumb_path).unwrap_or_default();
+
+    // Delete original image
+    if let Ok((_, original_path)) = open_original(room_path.join("original"), params.2) {
+        fs::remove_file(original_path).unwrap_or_default();
+    }
+
+    // Delete owner
+    fs::remove_file(owner_path(images_storage_path, params)).unwrap_or_default();
+
+    // Delete big image
+    let img_path = room_path.join(filename);
+    fs::remove_file(img_path).map_err(|err| match err.kind() {
+        ErrorKind::NotFound => String::from("Picture not found"),
+        _ => format!("Could not delete picture: {err}"),
+    })
 }
 
 fn hash_to_u32(hash: ImageHash) -> u32 {
@@ -217,6 +278,16 @@ pub fn img_id_to_filename(img_id: ImgId) -> String {
     format!("{}.{}", img_id, IMG_EXTENSION)
 }
 
+fn open_original<P: AsRef<Path>>(folder: P, img_id: ImgId) -> std::io::Result<(File, PathBuf)> {
+    // Originals keep the extension of the uploaded format
+    let stem = img_id.to_string();
+    let file_path = fs::read_dir(folder)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .find(|path| path.file_stem().is_some_and(|s| s.to_string_lossy() == stem))
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "File not found"))?;
+    File::open(&file_path).map(|file| (file, file_path))
+}
+
 fn open_img<P: AsRef<Path>>(base_name: P) -> std::io::Result<(File, PathBuf)> {
     // Fallback for jpg files
     let extensions = ["webp", "jpg"];
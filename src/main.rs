@@ -3,25 +3,33 @@ use actix_web::{
     App, HttpResponse, HttpServer,
     error::InternalError,
     middleware::Logger,
-    web::{Data, JsonConfig},
+    web::{self, Data, JsonConfig},
 };
 use api::{
-    delete_img, delete_lobby, delete_room, get_img_big, get_img_thumb, get_room_img_list,
-    get_room_list, handle_options, send_chat_message, test, upload_img,
+    create_upload_session, delete_img, delete_img_batch, delete_lobby, delete_room,
+    delete_room_img_batch, finalize_upload, get_img_big, get_img_original, get_img_thumb,
+    get_presence, get_room_img_list, get_room_list, get_upload_session, handle_options,
+    send_chat_message, test, upload_chunk, upload_img, upload_img_batch, upload_img_from_url,
 };
+use actix_multipart::form::MultipartFormConfig;
 use check::ImgChecker;
+use chunked_upload::ChunkCleaner;
 use config::{ServerConfig, cors_cfg, read_server_config};
 use log::{error, info};
 use notification::server::NotifyServer;
+use permission::{Action, rate_limit::RateLimited};
 use uuid::Uuid;
 
 mod api;
 mod check;
+mod chunked_upload;
 mod config;
 mod img;
 mod notification;
 mod permission;
 mod public_messages;
+mod upload;
+mod url_upload;
 mod utils;
 
 #[cfg(feature = "openssl")]
@@ -48,9 +56,12 @@ async fn main() -> std::io::Result<()> {
     let key_pem_path = server_cfg.key_pem_path.clone();
 
     // Live notifications server
-    let notify_server = Data::new(NotifyServer::new().start());
+    let notify_server = Data::new(NotifyServer::new(server_cfg.notifications.clone()).start());
     let img_checker = Data::new(ImgChecker::new(notify_server.clone(), server_cfg.clone()).start());
 
+    // Removes abandoned chunked uploads
+    ChunkCleaner::new(server_cfg.clone()).start();
+
     let res = HttpServer::new(move || {
         // json configuration
         let json_cfg = JsonConfig::default()
@@ -59,20 +70,32 @@ async fn main() -> std::io::Result<()> {
                 InternalError::from_response(err, HttpResponse::BadRequest().into()).into()
             });
 
+        // multipart configuration, big enough for one image and the form around it
+        let multipart_cfg =
+            MultipartFormConfig::default().total_limit(server_cfg.max_image_size_byte + 64 * 1024);
+
+        // only the batch upload may send a full batch of images
+        let batch_multipart_cfg = MultipartFormConfig::default().total_limit(
+            (server_cfg.max_image_size_byte + 64 * 1024) * server_cfg.max_batch_upload_files,
+        );
+
         // Create app
         App::new()
             // -------------
             // config
             // -------------
             .wrap(Logger::default())
-            .wrap(cors_cfg())
+            .wrap(cors_cfg(&server_cfg))
             .app_data(json_cfg)
+            .app_data(multipart_cfg)
             .app_data(server_cfg.clone())
             // -------------
             // Notifications
             // -------------
             .app_data(notify_server.clone())
             .service(notification::start_connection)
+            .service(notification::start_room_connection)
+            .service(notification::sse::start_event_stream)
             // -------------
             // After upload check
             // -------------
@@ -83,13 +106,28 @@ async fn main() -> std::io::Result<()> {
             .service(get_room_list)
             .service(get_room_img_list)
             .service(get_img_thumb)
+            .service(get_img_original)
             .service(get_img_big)
             .service(handle_options)
             .service(upload_img)
+            .service(
+                web::resource("/upload-batch/{lobby_id}/{room_id}")
+                    .app_data(batch_multipart_cfg)
+                    .wrap(RateLimited(Action::UploadImg))
+                    .route(web::post().to(upload_img_batch)),
+            )
+            .service(upload_img_from_url)
+            .service(create_upload_session)
+            .service(get_upload_session)
+            .service(upload_chunk)
+            .service(finalize_upload)
             .service(delete_room)
             .service(delete_lobby)
             .service(delete_img)
+            .service(delete_img_batch)
+            .service(delete_room_img_batch)
             .service(send_chat_message)
+            .service(get_presence)
             .service(test)
     });
 
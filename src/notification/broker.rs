@@ -0,0 +1,62 @@
+use crate::config::BrokerCfg;
+use super::{
+    internal_messages::{
+        ChatMessage, ImageDeleted, ImageUploaded, ImagesDeleted, LobbyDeleted, RoomDeleted,
+        SystemNotification,
+    },
+    redis::RedisBroker,
+    server::NotifyServer,
+};
+use actix::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Notification shared between the instances, every instance delivers it to its own sessions
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "()")]
+pub enum BusEvent {
+    ImageUploaded(ImageUploaded),
+    ImageDeleted(ImageDeleted),
+    ImagesDeleted(ImagesDeleted),
+    RoomDeleted(RoomDeleted),
+    LobbyDeleted(LobbyDeleted),
+    ChatMessage(ChatMessage),
+    SystemNotification(SystemNotification),
+}
+
+/// Bus event with the instance that sent it, so instances can skip their own events
+#[derive(Serialize, Deserialize)]
+pub struct Envelope {
+    pub origin: Uuid,
+    pub event: BusEvent,
+}
+
+pub enum Broker {
+    InProcess,
+    Redis(RedisBroker),
+}
+
+impl Broker {
+    /// Connects to the configured broker, events of other instances are sent to `notify_server`
+    pub fn start(cfg: &BrokerCfg, notify_server: Addr<NotifyServer>) -> Self {
+        match cfg {
+            BrokerCfg::InProcess => Broker::InProcess,
+            BrokerCfg::Redis(redis_cfg) => {
+                Broker::Redis(RedisBroker::start(redis_cfg.clone(), notify_server))
+            }
+        }
+    }
+
+    /// Whether other instances may send events
+    pub fn is_shared(&self) -> bool {
+        !matches!(self, Broker::InProcess)
+    }
+
+    /// Shares an event of this instance with the other instances
+    pub fn publish(&self, event: BusEvent) {
+        match self {
+            Broker::InProcess => {}
+            Broker::Redis(redis) => redis.publish(event),
+        }
+    }
+}
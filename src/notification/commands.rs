@@ -0,0 +1,213 @@
+use super::{
+    internal_messages::{Ack, ChatMessage, Subscribe, Typing, Unsubscribe},
+    server::NotifyServer,
+};
+use crate::{
+    ImgId, LobbyId, RoomId, SessionId,
+    config::ServerConfig,
+    img::get_filenames_as_img_id,
+    permission::{Action, Refusal, authorize},
+    public_messages::ws::{
+        CommandAckEvent, CommandErrorEvent, RoomSnapshotEvent, WsCommand, WsRequest,
+    },
+    utils::ParamTuple,
+};
+use actix::prelude::*;
+use actix_web::{HttpRequest, web::Data};
+use log::warn;
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+// typing events of a session are forwarded at most this often per room, and for a few rooms
+// within the interval
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+const MAX_TYPING_ROOMS: usize = 8;
+
+// typing reuses the last send_chat_message check this long
+const TYPING_PERMISSION_TTL: Duration = Duration::from_secs(60);
+
+enum CommandReply {
+    Ack,
+    RoomSnapshot(RoomSnapshotEvent),
+}
+
+/// Everything one websocket connection needs to answer the commands of its client. Permissions
+/// are checked against the request that opened the connection.
+pub struct CommandHandler {
+    notify_server: Data<Addr<NotifyServer>>,
+    cfg: Data<ServerConfig>,
+    req: HttpRequest,
+    pub session_id: SessionId,
+    lobby_id: LobbyId,
+    display_name: Option<String>,
+
+    // last forwarded typing event per room, `None` for the lobby
+    typing_sent: HashMap<Option<RoomId>, Instant>,
+
+    // last result of the send_chat_message permission, checked again after its ttl
+    chat_permission: Option<(Instant, Result<(), String>)>,
+}
+
+impl CommandHandler {
+    pub fn new(
+        notify_server: Data<Addr<NotifyServer>>,
+        cfg: Data<ServerConfig>,
+        req: HttpRequest,
+        session_id: SessionId,
+        lobby_id: LobbyId,
+        display_name: Option<String>,
+    ) -> Self {
+        Self {
+            notify_server,
+            cfg,
+            req,
+            session_id,
+            lobby_id,
+            display_name,
+            typing_sent: HashMap::new(),
+            chat_permission: None,
+        }
+    }
+
+    /// Executes one text message of the client and returns the reply for it
+    pub async fn handle(&mut self, text: &str) -> String {
+        let request: WsRequest = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(err) => return error_reply(None, &format!("Invalid command: {err}")),
+        };
+        let request_id = request.request_id;
+        let reply = match self.execute(request.command, request_id).await {
+            Ok(CommandReply::Ack) => serde_json::to_string(&CommandAckEvent {
+                event: "CommandAck",
+                request_id,
+            }),
+            Ok(CommandReply::RoomSnapshot(snapshot)) => serde_json::to_string(&snapshot),
+            Err(msg) => return error_reply(request_id, &msg),
+        };
+        reply.unwrap_or_else(|err| error_reply(request_id, &err.to_string()))
+    }
+
+    async fn execute(
+        &mut self,
+        command: WsCommand,
+        request_id: Option<u32>,
+    ) -> Result<CommandReply, String> {
+        match command {
+            WsCommand::SendChatMessage { msg } => {
+                let res = self
+                    .authorize(Action::SendChatMessage, &(self.lobby_id,))
+                    .await;
+                // a rate limited message doesn't say anything about the permission
+                if res.is_ok() {
+                    self.chat_permission = Some((Instant::now(), Ok(())));
+                }
+                res?;
+                let username = self
+                    .display_name
+                    .clone()
+                    .unwrap_or_else(|| String::from("User"));
+                self.send(ChatMessage::new(self.lobby_id, username, msg))
+                    .await?;
+            }
+            WsCommand::Subscribe { room_id } => {
+                // same rule as the websocket route of a room
+                self.authorize(Action::ConnectNotifications, &(self.lobby_id, room_id))
+                    .await?;
+                self.send(Subscribe::new(self.session_id, room_id)).await?;
+            }
+            WsCommand::Unsubscribe { room_id } => {
+                self.send(Unsubscribe::new(self.session_id, room_id))
+                    .await?;
+            }
+            WsCommand::Typing { room_id } => {
+                // Not rate limited or audited, clients send this on every key stroke. Events
+                // within the interval are dropped, the others see the session typing anyway.
+                let now = Instant::now();
+                self.typing_sent
+                    .retain(|_, sent| now.duration_since(*sent) < TYPING_INTERVAL);
+                if self.typing_sent.contains_key(&room_id)
+                    || self.typing_sent.len() >= MAX_TYPING_ROOMS
+                {
+                    return Ok(CommandReply::Ack);
+                }
+                // counts even if refused, so a refused client can't check on every key stroke
+                self.typing_sent.insert(room_id, now);
+                self.chat_permission().await?;
+                self.send(Typing::new(self.lobby_id, room_id, self.session_id))
+                    .await?;
+            }
+            WsCommand::GetRoomSnapshot { room_id } => {
+                self.authorize(Action::GetRoomImgList, &(self.lobby_id, room_id))
+                    .await?;
+                return Ok(CommandReply::RoomSnapshot(RoomSnapshotEvent {
+                    event: "RoomSnapshot",
+                    request_id,
+                    room_id,
+                    img_ids: self.room_img_ids(room_id),
+                }));
+            }
+            WsCommand::Ack { seq } => {
+                self.send(Ack::new(self.session_id, seq)).await?;
+            }
+        }
+        Ok(CommandReply::Ack)
+    }
+
+    /// Cached send_chat_message permission for typing events
+    async fn chat_permission(&mut self) -> Result<(), String> {
+        if let Some((checked, res)) = &self.chat_permission
+            && checked.elapsed() < TYPING_PERMISSION_TTL
+        {
+            return res.clone();
+        }
+        let res = self
+            .cfg
+            .permissions
+            .is_allowed(Action::SendChatMessage, &self.req, &(self.lobby_id,))
+            .await;
+        // a failed check is tried again with the next typing event
+        if res.as_ref().is_err_and(Refusal::is_failed) {
+            self.chat_permission = None;
+            return res.map_err(String::from);
+        }
+        let res = res.map_err(String::from);
+        self.chat_permission = Some((Instant::now(), res.clone()));
+        res
+    }
+
+    async fn authorize<T: ParamTuple>(&self, action: Action, params: &T) -> Result<(), String> {
+        authorize(&self.cfg.permissions, action, &self.req, params)
+            .await
+            .map_err(|denied| denied.to_string())
+    }
+
+    async fn send<M>(&self, msg: M) -> Result<(), String>
+    where
+        M: Message<Result = ()> + Send + 'static,
+        NotifyServer: Handler<M>,
+    {
+        self.notify_server.send(msg).await.map_err(|err| {
+            warn!("Can't reach notify server: {err}");
+            String::from("Notifications unavailable")
+        })
+    }
+
+    fn room_img_ids(&self, room_id: RoomId) -> Vec<ImgId> {
+        let folder_path = Path::new(&self.cfg.images_storage_path)
+            .join(self.lobby_id.to_string())
+            .join(room_id.to_string());
+        get_filenames_as_img_id(&folder_path).unwrap_or_default()
+    }
+}
+
+fn error_reply(request_id: Option<u32>, msg: &str) -> String {
+    serde_json::to_string(&CommandErrorEvent {
+        event: "CommandError",
+        request_id,
+        msg,
+    })
+    .unwrap_or_default()
+}
@@ -1,32 +1,66 @@
+use super::server::ConnectionId;
 use crate::SessionId;
 use crate::{
-    public_messages::ws::{
-        ChatMessageEvent, ConnectEvent, ImageProcessedEvent, LobbyDeletedEvent, RoomDeletedEvent,
-        SystemNotificationEvent,
+    public_messages::{
+        api::ImgRef,
+        ws::{
+            ChatMessageEvent, ImageProcessedEvent, ImagesDeletedEvent, LobbyDeletedEvent,
+            PresenceMember, RoomDeletedEvent, SystemNotificationEvent, TypingEvent,
+        },
     },
     utils::ToOutputJsonString,
     ImgId, LobbyId, RoomId,
 };
 use actix::prelude::*;
+use serde::{Deserialize, Serialize};
 use serde_json::Error;
 use std::fmt;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::broadcast::Sender;
 
-// WsConn sends this to the lobby to say "put me in please"
+/// Event for the sessions of one lobby. Lobby events are numbered and kept for a while, so
+/// reconnecting clients can get the events they missed.
+pub trait LobbyEvent {
+    fn lobby_id(&self) -> LobbyId;
+
+    /// Rooms the event is about, empty for events of the whole lobby
+    fn room_ids(&self) -> Vec<RoomId>;
+
+    fn to_output_json_string(&self, seq: u64) -> Result<String, Error>;
+}
+
+// WsConn sends this to the lobby to say "put me in please", answered with the session id
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "(SessionId, ConnectionId)")]
 pub struct Connect {
-    pub sender: UnboundedSender<String>,
+    pub sender: Sender<String>,
     pub lobby_id: LobbyId,
-    pub session_id: SessionId,
+
+    // room the session is subscribed to from the start
+    pub room_id: Option<RoomId>,
+
+    // last event seen before a reconnect, missed events are sent again
+    pub since: Option<u64>,
+
+    // verified session of an earlier connection the client wants to continue
+    pub resume: Option<SessionId>,
+
+    // the session may still have an open connection that is replaced then
+    pub take_over: bool,
+
+    // name shown to the other members of the lobby
+    pub display_name: Option<String>,
 }
 
-impl ToOutputJsonString for Connect {
-    fn to_output_json_string(&self) -> Result<String, Error> {
-        serde_json::to_string(&ConnectEvent {
-            event: "Connected",
-            session_id: self.session_id,
-        })
+// members of a lobby for the presence endpoint
+#[derive(Message)]
+#[rtype(result = "Vec<PresenceMember>")]
+pub struct GetPresence {
+    pub lobby_id: LobbyId,
+}
+
+impl GetPresence {
+    pub fn new(lobby_id: LobbyId) -> Self {
+        Self { lobby_id }
     }
 }
 
@@ -35,17 +69,70 @@ impl ToOutputJsonString for Connect {
 #[rtype(result = "()")]
 pub struct Disconnect {
     pub session_id: SessionId,
+
+    // ignored if the session already continues with another connection
+    pub connection_id: ConnectionId,
 }
 
 impl Disconnect {
-    pub fn new(session_id: SessionId) -> Self {
-        Self { session_id }
+    pub fn new(session_id: SessionId, connection_id: ConnectionId) -> Self {
+        Self {
+            session_id,
+            connection_id,
+        }
+    }
+}
+
+// session only wants the events of these rooms
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Subscribe {
+    pub session_id: SessionId,
+    pub room_id: RoomId,
+}
+
+impl Subscribe {
+    pub fn new(session_id: SessionId, room_id: RoomId) -> Self {
+        Self {
+            session_id,
+            room_id,
+        }
     }
 }
 
-// image was uploaded
 #[derive(Message)]
 #[rtype(result = "()")]
+pub struct Unsubscribe {
+    pub session_id: SessionId,
+    pub room_id: RoomId,
+}
+
+impl Unsubscribe {
+    pub fn new(session_id: SessionId, room_id: RoomId) -> Self {
+        Self {
+            session_id,
+            room_id,
+        }
+    }
+}
+
+// session received the events up to this seq, resumed from there if it reconnects without since
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Ack {
+    pub session_id: SessionId,
+    pub seq: u64,
+}
+
+impl Ack {
+    pub fn new(session_id: SessionId, seq: u64) -> Self {
+        Self { session_id, seq }
+    }
+}
+
+// image was uploaded
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "()")]
 pub struct ImageUploaded {
     pub lobby_id: LobbyId,
     pub room_id: RoomId,
@@ -62,10 +149,19 @@ impl ImageUploaded {
     }
 }
 
-impl ToOutputJsonString for ImageUploaded {
-    fn to_output_json_string(&self) -> Result<String, serde_json::Error> {
+impl LobbyEvent for ImageUploaded {
+    fn lobby_id(&self) -> LobbyId {
+        self.lobby_id
+    }
+
+    fn room_ids(&self) -> Vec<RoomId> {
+        vec![self.room_id]
+    }
+
+    fn to_output_json_string(&self, seq: u64) -> Result<String, Error> {
         serde_json::to_string(&ImageProcessedEvent {
             event: "ImageUploaded",
+            seq,
             room_id: self.room_id,
             img_id: self.img_id,
         })
@@ -73,7 +169,7 @@ impl ToOutputJsonString for ImageUploaded {
 }
 
 // image was uploaded
-#[derive(Message)]
+#[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "()")]
 pub struct ImageDeleted {
     pub lobby_id: LobbyId,
@@ -91,17 +187,58 @@ impl ImageDeleted {
     }
 }
 
-impl ToOutputJsonString for ImageDeleted {
-    fn to_output_json_string(&self) -> Result<String, serde_json::Error> {
+impl LobbyEvent for ImageDeleted {
+    fn lobby_id(&self) -> LobbyId {
+        self.lobby_id
+    }
+
+    fn room_ids(&self) -> Vec<RoomId> {
+        vec![self.room_id]
+    }
+
+    fn to_output_json_string(&self, seq: u64) -> Result<String, Error> {
         serde_json::to_string(&ImageProcessedEvent {
             event: "ImageDeleted",
+            seq,
             room_id: self.room_id,
             img_id: self.img_id,
         })
     }
 }
 
-#[derive(Message)]
+// multiple images were deleted at once
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "()")]
+pub struct ImagesDeleted {
+    pub lobby_id: LobbyId,
+    pub images: Vec<ImgRef>,
+}
+
+impl ImagesDeleted {
+    pub fn new(lobby_id: LobbyId, images: Vec<ImgRef>) -> Self {
+        Self { lobby_id, images }
+    }
+}
+
+impl LobbyEvent for ImagesDeleted {
+    fn lobby_id(&self) -> LobbyId {
+        self.lobby_id
+    }
+
+    fn room_ids(&self) -> Vec<RoomId> {
+        self.images.iter().map(|img| img.room_id).collect()
+    }
+
+    fn to_output_json_string(&self, seq: u64) -> Result<String, Error> {
+        serde_json::to_string(&ImagesDeletedEvent {
+            event: "ImagesDeleted",
+            seq,
+            images: &self.images,
+        })
+    }
+}
+
+#[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "()")]
 pub struct RoomDeleted {
     pub lobby_id: LobbyId,
@@ -114,16 +251,25 @@ impl RoomDeleted {
     }
 }
 
-impl ToOutputJsonString for RoomDeleted {
-    fn to_output_json_string(&self) -> Result<String, serde_json::Error> {
+impl LobbyEvent for RoomDeleted {
+    fn lobby_id(&self) -> LobbyId {
+        self.lobby_id
+    }
+
+    fn room_ids(&self) -> Vec<RoomId> {
+        vec![self.room_id]
+    }
+
+    fn to_output_json_string(&self, seq: u64) -> Result<String, Error> {
         serde_json::to_string(&RoomDeletedEvent {
             event: "RoomDeleted",
+            seq,
             room_id: self.room_id,
         })
     }
 }
 
-#[derive(Message)]
+#[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "()")]
 pub struct LobbyDeleted {
     pub lobby_id: LobbyId,
@@ -135,15 +281,24 @@ impl LobbyDeleted {
     }
 }
 
-impl ToOutputJsonString for LobbyDeleted {
-    fn to_output_json_string(&self) -> Result<String, serde_json::Error> {
+impl LobbyEvent for LobbyDeleted {
+    fn lobby_id(&self) -> LobbyId {
+        self.lobby_id
+    }
+
+    fn room_ids(&self) -> Vec<RoomId> {
+        Vec::new()
+    }
+
+    fn to_output_json_string(&self, seq: u64) -> Result<String, Error> {
         serde_json::to_string(&LobbyDeletedEvent {
             event: "LobbyDeleted",
+            seq,
         })
     }
 }
 
-#[derive(Message)]
+#[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "()")]
 pub struct ChatMessage {
     pub lobby_id: LobbyId,
@@ -161,17 +316,55 @@ impl ChatMessage {
     }
 }
 
-impl ToOutputJsonString for ChatMessage {
-    fn to_output_json_string(&self) -> Result<String, serde_json::Error> {
+impl LobbyEvent for ChatMessage {
+    fn lobby_id(&self) -> LobbyId {
+        self.lobby_id
+    }
+
+    fn room_ids(&self) -> Vec<RoomId> {
+        Vec::new()
+    }
+
+    fn to_output_json_string(&self, seq: u64) -> Result<String, Error> {
         serde_json::to_string(&ChatMessageEvent {
             event: "ChatMessage",
+            seq,
             username: &self.username,
             msg: &self.msg,
         })
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+// user is typing a chat message, sent to everybody else in the lobby
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Typing {
+    pub lobby_id: LobbyId,
+    pub room_id: Option<RoomId>,
+    pub session_id: SessionId,
+}
+
+impl Typing {
+    pub fn new(lobby_id: LobbyId, room_id: Option<RoomId>, session_id: SessionId) -> Self {
+        Self {
+            lobby_id,
+            room_id,
+            session_id,
+        }
+    }
+}
+
+impl ToOutputJsonString for Typing {
+    fn to_output_json_string(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&TypingEvent {
+            event: "Typing",
+            session_id: self.session_id,
+            room_id: self.room_id,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum SystemNotificationType {
     Warning,
 }
@@ -182,7 +375,7 @@ impl fmt::Display for SystemNotificationType {
     }
 }
 
-#[derive(Message)]
+#[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "()")]
 pub struct SystemNotification {
     pub session_id: SessionId,
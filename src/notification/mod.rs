@@ -1,9 +1,15 @@
-use crate::{utils::SESSION_COOKIE_NAME, LobbyId, SessionId};
+use crate::{
+    config::{NotificationCfg, ServerConfig},
+    permission::{check, rate_limit::RateLimited, Action},
+    utils::SESSION_COOKIE_NAME,
+    LobbyId, RoomId,
+};
 use actix::prelude::*;
 use actix_web::{
-    cookie::{Cookie, SameSite},
+    cookie::{time::Duration as CookieDuration, Cookie, SameSite},
+    error::ErrorServiceUnavailable,
     get,
-    web::{Data, Path, Payload},
+    web::{Data, Path, Payload, Query},
     Error, HttpRequest, HttpResponse,
 };
 use actix_ws::AggregatedMessage;
@@ -11,79 +17,209 @@ use futures_util::{
     future::{select, Either},
     StreamExt as _,
 };
+use commands::CommandHandler;
 use internal_messages::{Connect, Disconnect};
-use server::NotifyServer;
+use serde::Deserialize;
+use server::{ConnectionId, NotifyServer};
+use session::{sign_session_id, verify_session_token};
 use std::{
     pin::pin,
     time::{Duration, Instant},
 };
-use tokio::{sync::mpsc, task::spawn_local, time::interval};
-use uuid::Uuid;
+use tokio::{
+    sync::broadcast::{self, error::RecvError, Receiver},
+    task::spawn_local,
+    time::{interval, timeout},
+};
 
+pub mod broker;
+pub mod commands;
 pub mod internal_messages;
+pub mod redis;
 pub mod server;
+pub mod session;
+pub mod sse;
+pub mod webhook;
 
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+
+#[derive(Deserialize)]
+struct ConnectQuery {
+    // last event `seq` the client has seen, newer events are sent again
+    since: Option<u64>,
+
+    // `session_token` of an earlier connection to resume
+    session: Option<String>,
+
+    // name shown to the other members of the lobby
+    name: Option<String>,
+}
 
 /// Entry point for our websocket route
-#[get("/notifications/{lobby_id}")]
+#[get("/notifications/{lobby_id}", wrap = "RateLimited(Action::ConnectNotifications)")]
 pub async fn start_connection(
     req: HttpRequest,
     path: Path<(LobbyId,)>,
     stream: Payload,
     notify_server: Data<Addr<NotifyServer>>,
+    cfg: Data<ServerConfig>,
+) -> Result<HttpResponse, Error> {
+    connect(req, stream, path.0, None, notify_server, cfg).await
+}
+
+/// Websocket route subscribed to one room from the start, lobby-wide events are sent too
+#[get("/notifications/{lobby_id}/{room_id}", wrap = "RateLimited(Action::ConnectNotifications)")]
+pub async fn start_room_connection(
+    req: HttpRequest,
+    path: Path<(LobbyId, RoomId)>,
+    stream: Payload,
+    notify_server: Data<Addr<NotifyServer>>,
+    cfg: Data<ServerConfig>,
+) -> Result<HttpResponse, Error> {
+    let (lobby_id, room_id) = path.into_inner();
+    connect(req, stream, lobby_id, Some(room_id), notify_server, cfg).await
+}
+
+/// Opens the websocket and joins the lobby
+async fn connect(
+    req: HttpRequest,
+    stream: Payload,
+    lobby_id: LobbyId,
+    room_id: Option<RoomId>,
+    notify_server: Data<Addr<NotifyServer>>,
+    cfg: Data<ServerConfig>,
 ) -> Result<HttpResponse, Error> {
+    // check permission before the connection is upgraded
+    let denied = match room_id {
+        Some(room_id) => {
+            check(&cfg.permissions, Action::ConnectNotifications, &req, &(lobby_id, room_id)).await
+        }
+        None => check(&cfg.permissions, Action::ConnectNotifications, &req, &(lobby_id,)).await,
+    };
+    if let Some(err) = denied {
+        return Ok(err);
+    }
+
+    let (connect_msg, conn_rx) = connect_msg(&req, &cfg, lobby_id, room_id).await;
+    let display_name = connect_msg.display_name.clone();
+
     let (mut res, session, msg_stream) = actix_ws::handle(&req, stream)?;
-    let session_id: SessionId = Uuid::new_v4();
+    let (session_id, connection_id) = notify_server
+        .send(connect_msg)
+        .await
+        .map_err(ErrorServiceUnavailable)?;
+    let token = sign_session_id(&cfg.notifications, session_id);
+    let cookie = session_cookie(&cfg.notifications, &token);
+    let commands = CommandHandler::new(
+        notify_server.clone(),
+        cfg,
+        req.clone(),
+        session_id,
+        lobby_id,
+        display_name,
+    );
 
     // spawn websocket handler (and don't await it) so that the response is returned immediately
     spawn_local(chat_ws(
         notify_server,
         session,
         msg_stream,
-        session_id,
-        path.0,
+        conn_rx,
+        connection_id,
+        commands,
     ));
 
-    let cookie = Cookie::build(SESSION_COOKIE_NAME, format!("{session_id}; Partitioned"))
+    res.add_cookie(&cookie)?;
+
+    Ok(res)
+}
+
+/// Join message for the notify server and the queue of the new connection. A session is
+/// resumed with the `session` query parameter, which may replace a connection that is still
+/// open, or with the session cookie.
+async fn connect_msg(
+    req: &HttpRequest,
+    cfg: &ServerConfig,
+    lobby_id: LobbyId,
+    room_id: Option<RoomId>,
+) -> (Connect, Receiver<String>) {
+    let query = Query::<ConnectQuery>::from_query(req.query_string())
+        .ok()
+        .map(Query::into_inner);
+    let since = query.as_ref().and_then(|query| query.since);
+    let query_name = query.as_ref().and_then(|query| query.name.clone());
+    let query_token = query.and_then(|query| query.session);
+    let take_over = query_token.is_some();
+    let resume = query_token
+        .or_else(|| {
+            req.cookie(SESSION_COOKIE_NAME)
+                .map(|cookie| cookie.value().to_string())
+        })
+        .and_then(|token| verify_session_token(&cfg.notifications, &token));
+    let display_name = display_name(req, cfg, lobby_id, query_name).await;
+    let (conn_tx, conn_rx) = broadcast::channel(cfg.notifications.session_queue_size.max(1));
+    let connect_msg = Connect {
+        sender: conn_tx,
+        lobby_id,
+        room_id,
+        since,
+        resume,
+        take_over,
+        display_name,
+    };
+    (connect_msg, conn_rx)
+}
+
+/// Only sent back to the server, clients resume with the `session_token` of `Connected`
+fn session_cookie(cfg: &NotificationCfg, token: &str) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE_NAME, format!("{token}; Partitioned"))
         .path("/")
-        .http_only(false)
+        .max_age(CookieDuration::seconds(cfg.session_token_ttl_sec as i64))
+        .http_only(true)
         .secure(true)
         .same_site(SameSite::None)
-        .finish();
-
-    res.add_cookie(&cookie)?;
+        .finish()
+}
 
-    Ok(res)
+/// Name from the identity confirmation if configured, otherwise the `name` query parameter
+async fn display_name(
+    req: &HttpRequest,
+    cfg: &ServerConfig,
+    lobby_id: LobbyId,
+    query_name: Option<String>,
+) -> Option<String> {
+    let name = match &cfg.notifications.identity {
+        Some(identity) => identity
+            .identify(req, &(lobby_id,))
+            .await
+            .unwrap_or_else(|err| {
+                log::warn!("Can't identify client: {err}");
+                None
+            }),
+        None => query_name,
+    }?;
+    let name: String = name.trim().chars().take(MAX_DISPLAY_NAME_LEN).collect();
+    (!name.is_empty()).then_some(name)
 }
 
-/// Echo text & binary messages received from the client, respond to ping messages, and monitor
+/// Answer text commands received from the client, respond to ping messages, and monitor
 /// connection health to detect network issues and free up resources.
 pub async fn chat_ws(
     notify_server: Data<Addr<NotifyServer>>,
     mut session: actix_ws::Session,
     msg_stream: actix_ws::MessageStream,
-    session_id: Uuid,
-    lobby_id: LobbyId,
+    mut conn_rx: Receiver<String>,
+    connection_id: ConnectionId,
+    mut commands: CommandHandler,
 ) {
     log::info!("connected");
 
+    let session_id = commands.session_id;
     let mut last_heartbeat = Instant::now();
     let mut interval = interval(HEARTBEAT_INTERVAL);
 
-    let (conn_tx, mut conn_rx) = mpsc::unbounded_channel();
-
-    // unwrap: chat server is not dropped before the HTTP server
-    notify_server
-        .send(Connect {
-            sender: conn_tx,
-            lobby_id,
-            session_id,
-        })
-        .await
-        .expect("Can connect");
-
     let msg_stream = msg_stream
         .max_frame_size(128 * 1024)
         .aggregate_continuations()
@@ -98,39 +234,61 @@ pub async fn chat_ws(
         let msg_rx = pin!(conn_rx.recv());
 
         // TODO: nested select is pretty gross for readability on the match
-        let messages = pin!(select(msg_stream.next(), msg_rx));
+        // messages for the client first, so a flood of commands can't fill up its queue
+        let messages = pin!(select(msg_rx, msg_stream.next()));
 
         match select(messages, tick).await {
             // messages received from client
-            Either::Left((Either::Left((Some(Ok(msg)), _)), _)) => match msg {
+            Either::Left((Either::Right((Some(Ok(msg)), _)), _)) => match msg {
                 AggregatedMessage::Ping(bytes) => {
                     last_heartbeat = Instant::now();
-                    session.pong(&bytes).await.unwrap();
+                    if session.pong(&bytes).await.is_err() {
+                        break None;
+                    }
                 }
                 AggregatedMessage::Pong(_) => last_heartbeat = Instant::now(),
-                AggregatedMessage::Text(_text) => log::warn!("unexpected text message"),
+                AggregatedMessage::Text(text) => {
+                    let reply = commands.handle(&text).await;
+                    if session.text(reply).await.is_err() {
+                        break None;
+                    }
+                }
                 AggregatedMessage::Binary(_bin) => log::warn!("unexpected binary message"),
                 AggregatedMessage::Close(reason) => break reason,
             },
 
             // client WebSocket stream error
-            Either::Left((Either::Left((Some(Err(err)), _)), _)) => {
+            Either::Left((Either::Right((Some(Err(err)), _)), _)) => {
                 log::error!("{}", err);
                 break None;
             }
 
             // client WebSocket stream ended
-            Either::Left((Either::Left((None, _)), _)) => break None,
+            Either::Left((Either::Right((None, _)), _)) => break None,
 
             // chat messages received from other room participants
-            Either::Left((Either::Right((Some(chat_msg), _)), _)) => {
-                session.text(chat_msg).await.unwrap();
+            Either::Left((Either::Left((Ok(chat_msg), _)), _)) => {
+                // a client that stopped reading blocks the send, give up after the client timeout
+                match timeout(CLIENT_TIMEOUT, session.text(chat_msg)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(_)) => break None,
+                    Err(_) => {
+                        log::info!("client not reading for {CLIENT_TIMEOUT:?}; disconnecting");
+                        break None;
+                    }
+                }
+            }
+
+            // the client is too slow, the oldest messages were dropped
+            Either::Left((Either::Left((Err(RecvError::Lagged(skipped)), _)), _)) => {
+                log::warn!("client too slow; {skipped} messages dropped");
             }
 
-            // all connection's message senders were dropped
-            Either::Left((Either::Right((None, _)), _)) => unreachable!(
-                "all connection message senders were dropped; chat server may have panicked"
-            ),
+            // the server closed the connection, the session continues elsewhere or was too slow
+            Either::Left((Either::Left((Err(RecvError::Closed), _)), _)) => {
+                log::info!("connection closed by notify server; disconnecting");
+                break None;
+            }
 
             // heartbeat internal tick
             Either::Right((_inst, _)) => {
@@ -148,11 +306,13 @@ pub async fn chat_ws(
         };
     };
 
-    notify_server
-        .send(Disconnect::new(session_id))
+    if let Err(err) = notify_server
+        .send(Disconnect::new(session_id, connection_id))
         .await
-        .expect("Can disconnect");
+    {
+        log::warn!("Can't disconnect session {session_id}: {err}");
+    }
 
     // attempt to close connection gracefully
-    let _ = session.close(close_reason).await;
+    let _ = timeout(CLIENT_TIMEOUT, session.close(close_reason)).await;
 }
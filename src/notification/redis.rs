@@ -0,0 +1,155 @@
+use crate::config::RedisCfg;
+use super::{
+    broker::{BusEvent, Envelope},
+    server::NotifyServer,
+};
+use actix::prelude::*;
+use futures_util::StreamExt as _;
+use log::{info, warn};
+use redis::{AsyncCommands as _, Client, RedisResult, aio::MultiplexedConnection};
+use std::time::Duration;
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::sleep,
+};
+use uuid::Uuid;
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Publishes the events of this instance and forwards the events of the other instances to the
+/// notify server. Both directions use their own connection and reconnect on errors.
+pub struct RedisBroker {
+    origin: Uuid,
+    sender: mpsc::Sender<String>,
+}
+
+impl RedisBroker {
+    pub fn start(cfg: RedisCfg, notify_server: Addr<NotifyServer>) -> Self {
+        let origin = Uuid::new_v4();
+        let (sender, receiver) = mpsc::channel(cfg.queue_size.max(1));
+
+        // the url is parsed by the client: redis:// or rediss://, password and database
+        match Client::open(cfg.url.as_str()) {
+            Ok(client) => {
+                actix::spawn(publish_loop(client.clone(), cfg.channel.clone(), receiver));
+                actix::spawn(subscribe_loop(client, cfg.channel, origin, notify_server));
+            }
+            Err(err) => warn!("Invalid redis url, events are not shared: {err}"),
+        }
+        Self { origin, sender }
+    }
+
+    pub fn publish(&self, event: BusEvent) {
+        let envelope = Envelope {
+            origin: self.origin,
+            event,
+        };
+        let Ok(payload) = serde_json::to_string(&envelope) else {
+            warn!("Can't parse bus event to json");
+            return;
+        };
+        match self.sender.try_send(payload) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Redis publish queue full, event dropped"),
+            Err(TrySendError::Closed(_)) => warn!("Redis publisher stopped, event dropped"),
+        }
+    }
+}
+
+async fn publish_loop(client: Client, channel: String, mut receiver: mpsc::Receiver<String>) {
+    let mut connection: Option<MultiplexedConnection> = None;
+    'events: while let Some(payload) = receiver.recv().await {
+        // a broken connection is only noticed when used, so the event gets a second try
+        for _ in 0..2 {
+            if connection.is_none() {
+                connection = client
+                    .get_multiplexed_async_connection()
+                    .await
+                    .inspect_err(|err| warn!("Can't connect to redis for publishing: {err}"))
+                    .ok();
+            }
+            let Some(conn) = connection.as_mut() else {
+                break;
+            };
+            match conn.publish::<_, _, ()>(&channel, &payload).await {
+                Ok(()) => continue 'events,
+                Err(err) => {
+                    warn!("Can't publish event to redis: {err}");
+                    connection = None;
+                }
+            }
+        }
+        warn!("Event not shared with the other instances");
+    }
+}
+
+async fn subscribe_loop(
+    client: Client,
+    channel: String,
+    origin: Uuid,
+    notify_server: Addr<NotifyServer>,
+) {
+    let mut delay = MIN_RECONNECT_DELAY;
+    while notify_server.connected() {
+        match subscribe(&client, &channel, origin, &notify_server, &mut delay).await {
+            Ok(()) => warn!("Redis subscription closed, retrying in {delay:?}"),
+            Err(err) => warn!("Redis subscription lost, retrying in {delay:?}: {err}"),
+        }
+        sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Forwards the messages of the channel until the connection is closed
+async fn subscribe(
+    client: &Client,
+    channel: &str,
+    origin: Uuid,
+    notify_server: &Addr<NotifyServer>,
+    delay: &mut Duration,
+) -> RedisResult<()> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(channel).await?;
+    info!("Subscribed to redis channel {channel}");
+    *delay = MIN_RECONNECT_DELAY;
+
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        match serde_json::from_slice::<Envelope>(msg.get_payload_bytes()) {
+            Ok(envelope) if envelope.origin == origin => {}
+            Ok(envelope) => notify_server.do_send(envelope.event),
+            Err(err) => warn!("Can't parse bus event: {err}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use tokio::time::timeout;
+
+    /// Needs a running Redis, e.g. `WIM_TEST_REDIS_URL=redis://127.0.0.1:6379 cargo test -- --ignored`
+    #[actix::test]
+    #[ignore]
+    async fn publishes_and_receives_through_redis() {
+        let url = env::var("WIM_TEST_REDIS_URL").unwrap_or(String::from("redis://127.0.0.1"));
+        let channel = format!("wim-test-{}", Uuid::new_v4());
+        let client = Client::open(url.as_str()).unwrap();
+
+        let mut pubsub = client.get_async_pubsub().await.unwrap();
+        pubsub.subscribe(&channel).await.unwrap();
+        let (sender, receiver) = mpsc::channel(4);
+        actix::spawn(publish_loop(client, channel, receiver));
+        sender.send(String::from("{\"test\":1}")).await.unwrap();
+
+        let mut messages = pubsub.on_message();
+        let msg = timeout(Duration::from_secs(5), messages.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.get_payload_bytes(), b"{\"test\":1}");
+    }
+}
@@ -1,69 +1,463 @@
+use super::broker::{Broker, BusEvent};
 use super::internal_messages::{
-    ChatMessage, Connect, Disconnect, ImageDeleted, ImageUploaded, LobbyDeleted, RoomDeleted,
-    SystemNotification,
+    Ack, ChatMessage, Connect, Disconnect, GetPresence, ImageDeleted, ImageUploaded, ImagesDeleted,
+    LobbyDeleted, LobbyEvent, RoomDeleted, Subscribe, SystemNotification, Typing, Unsubscribe,
+};
+use super::session::sign_session_id;
+use super::webhook::Webhooks;
+use crate::{
+    config::{NotificationCfg, SlowConsumerPolicy},
+    public_messages::ws::{
+        ConnectEvent, PresenceEvent, PresenceMember, ResyncRequiredEvent, UserJoinedEvent,
+        UserLeftEvent,
+    },
+    utils::ToOutputJsonString,
+    LobbyId, RoomId,
 };
-use crate::{utils::ToOutputJsonString, LobbyId};
 use actix::prelude::*;
 use log::{debug, warn};
-use std::collections::{HashMap, HashSet};
-use tokio::sync::mpsc::UnboundedSender;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast::Sender;
 use uuid::Uuid;
 
 type SessionId = Uuid;
+pub type ConnectionId = u64;
 
 pub struct NotifyServer {
-    sessions: HashMap<SessionId, UnboundedSender<String>>,
+    sessions: HashMap<SessionId, Connection>,
     lobbies: HashMap<LobbyId, HashSet<SessionId>>,
+
+    // rooms whose events a session gets, sessions without entry get every room
+    subscriptions: HashMap<SessionId, Subscriptions>,
+
+    // recent events of the lobbies with connected or detached sessions
+    event_logs: HashMap<LobbyId, EventLog>,
+
+    // highest seq of the dropped event logs, new logs continue after it, so a seq of a dropped
+    // log can't point into a new one
+    dropped_seq: u64,
+
+    // sessions without connection, they can be resumed until the grace period is over
+    detached: HashMap<SessionId, DetachedSession>,
+
+    // names of the sessions shown in the presence of their lobby
+    display_names: HashMap<SessionId, String>,
+
+    // highest seq acknowledged by each session, the default `since` when it is resumed
+    acked_seqs: HashMap<SessionId, u64>,
+
+    next_connection_id: ConnectionId,
+
+    // shares the events with the other server instances
+    broker: Broker,
+
+    // posts the events of this instance to the subscribed backends
+    webhooks: Webhooks,
+
+    cfg: NotificationCfg,
+}
+
+/// Open websocket of a session, a resumed session gets a new connection
+struct Connection {
+    id: ConnectionId,
+    sender: Sender<String>,
+}
+
+struct DetachedSession {
+    lobby_id: LobbyId,
+    subscriptions: Subscriptions,
+    since: Instant,
+
+    // messages for this session only, delivered on resume
+    pending: Vec<String>,
+}
+
+/// Rooms whose events a session receives, lobby-wide events are always sent
+#[derive(Default)]
+enum Subscriptions {
+    // every room, until the session subscribes to one
+    #[default]
+    All,
+
+    // only these rooms, none after unsubscribing from the last one
+    Rooms(HashSet<RoomId>),
+}
+
+impl Subscriptions {
+    fn subscribe(&mut self, room_id: RoomId) {
+        match self {
+            Subscriptions::All => *self = Subscriptions::Rooms(HashSet::from([room_id])),
+            Subscriptions::Rooms(rooms) => {
+                rooms.insert(room_id);
+            }
+        }
+    }
+
+    /// Only removes subscribed rooms, a session with every room keeps them
+    fn unsubscribe(&mut self, room_id: RoomId) {
+        if let Subscriptions::Rooms(rooms) = self {
+            rooms.remove(&room_id);
+        }
+    }
+
+    fn contains_any(&self, room_ids: &[RoomId]) -> bool {
+        match self {
+            Subscriptions::All => true,
+            Subscriptions::Rooms(rooms) => room_ids.iter().any(|room_id| rooms.contains(room_id)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct EventLog {
+    last_seq: u64,
+    events: VecDeque<LoggedEvent>,
+}
+
+struct LoggedEvent {
+    seq: u64,
+    room_ids: Vec<RoomId>,
+    json: String,
 }
 
 impl NotifyServer {
-    pub fn new() -> NotifyServer {
+    pub fn new(cfg: NotificationCfg) -> NotifyServer {
         debug!("Server instance created");
         NotifyServer {
             sessions: HashMap::new(),
             lobbies: HashMap::new(),
+            subscriptions: HashMap::new(),
+            event_logs: HashMap::new(),
+            dropped_seq: 0,
+            detached: HashMap::new(),
+            display_names: HashMap::new(),
+            acked_seqs: HashMap::new(),
+            next_connection_id: 0,
+            broker: Broker::InProcess,
+            webhooks: Webhooks::default(),
+            cfg,
+        }
+    }
+
+    /// Numbers the event, remembers it for reconnects and sends it to the interested sessions
+    fn publish<E: LobbyEvent>(&mut self, event: &E) -> Option<String> {
+        let lobby_id = event.lobby_id();
+        let dropped_seq = self.dropped_seq;
+        let log = self.event_logs.entry(lobby_id).or_insert_with(|| EventLog {
+            last_seq: dropped_seq,
+            events: VecDeque::new(),
+        });
+        let seq = log.last_seq + 1;
+        let Ok(json) = event.to_output_json_string(seq) else {
+            warn!("Can't parse lobby event to json");
+            return None;
+        };
+        log.last_seq = seq;
+        let room_ids = event.room_ids();
+        if self.cfg.replay_buffer_size > 0 {
+            if log.events.len() >= self.cfg.replay_buffer_size {
+                log.events.pop_front();
+            }
+            log.events.push_back(LoggedEvent {
+                seq,
+                room_ids: room_ids.clone(),
+                json: json.clone(),
+            });
+        }
+        self.send_msg_to_rooms(&lobby_id, &room_ids, &json);
+        self.drop_unused_log(&lobby_id);
+        Some(json)
+    }
+
+    fn last_seq(&self, lobby_id: &LobbyId) -> u64 {
+        self.event_logs
+            .get(lobby_id)
+            .map_or(self.dropped_seq, |log| log.last_seq)
+    }
+
+    fn drop_log(&mut self, lobby_id: &LobbyId) {
+        if let Some(log) = self.event_logs.remove(lobby_id) {
+            self.dropped_seq = self.dropped_seq.max(log.last_seq);
         }
     }
 
-    fn send_msg_to_lobby(&self, lobby_id: &LobbyId, msg: &str) {
+    /// Drops the event log of a lobby nobody can resume in anymore
+    fn drop_unused_log(&mut self, lobby_id: &LobbyId) {
+        let in_use = self.lobbies.contains_key(lobby_id)
+            || self
+                .detached
+                .values()
+                .any(|detached| detached.lobby_id == *lobby_id);
+        if !in_use {
+            self.drop_log(lobby_id);
+        }
+    }
+
+    /// Publishes an event of this instance, which is posted to the webhooks too
+    fn emit<E: LobbyEvent>(&mut self, event: &E) {
+        if let Some(json) = self.publish(event) {
+            self.webhooks.post(event.lobby_id(), &json);
+        }
+    }
+
+    /// Session id and pending messages of the session the client wants to continue. Sessions
+    /// without connection can be resumed within the grace period, sessions with an open
+    /// connection only with `take_over`. The session has to stay in its lobby.
+    fn resume(&mut self, msg: &Connect) -> Option<(SessionId, Vec<String>)> {
+        let session_id = msg.resume?;
+        if self
+            .detached
+            .get(&session_id)
+            .is_some_and(|detached| detached.lobby_id == msg.lobby_id)
+        {
+            let detached = self.detached.remove(&session_id)?;
+            self.subscriptions.insert(session_id, detached.subscriptions);
+            return Some((session_id, detached.pending));
+        }
+        let in_lobby = self
+            .lobbies
+            .get(&msg.lobby_id)
+            .is_some_and(|lobby| lobby.contains(&session_id));
+        (msg.take_over && in_lobby).then_some((session_id, Vec::new()))
+    }
+
+    /// Connected sessions of the lobby and the ones that can still be resumed
+    fn members(&self, lobby_id: &LobbyId) -> Vec<PresenceMember> {
+        let connected = self.lobbies.get(lobby_id).into_iter().flatten();
+        let detached = self
+            .detached
+            .iter()
+            .filter(|(_, detached)| detached.lobby_id == *lobby_id)
+            .map(|(session_id, _)| session_id);
+        connected
+            .chain(detached)
+            .map(|session_id| PresenceMember {
+                session_id: *session_id,
+                display_name: self.display_names.get(session_id).cloned(),
+            })
+            .collect()
+    }
+
+    fn send_msg_to_others(&mut self, lobby_id: &LobbyId, session_id: &SessionId, msg: &str) {
+        let others: Vec<SessionId> = self
+            .lobbies
+            .get(lobby_id)
+            .into_iter()
+            .flatten()
+            .filter(|other_id| *other_id != session_id)
+            .copied()
+            .collect();
+        for other_id in others {
+            self.send_msg_to_user(&other_id, msg);
+        }
+    }
+
+    /// Removes what is left of a session that can't be resumed anymore
+    fn forget(&mut self, session_id: SessionId, lobby_id: &LobbyId) {
+        self.display_names.remove(&session_id);
+        self.acked_seqs.remove(&session_id);
+        let left = serde_json::to_string(&UserLeftEvent {
+            event: "UserLeft",
+            session_id,
+        });
+        match left {
+            Ok(left) => self.send_msg_to_others(lobby_id, &session_id, &left),
+            Err(_) => warn!("Can't parse user left event to json"),
+        }
+        self.drop_unused_log(lobby_id);
+    }
+
+    /// Sends the events after `since` again, or `ResyncRequired` if they are no longer known
+    fn replay(&mut self, session_id: &SessionId, lobby_id: &LobbyId, since: u64) {
+        let last_seq = self.last_seq(lobby_id);
+        let log = self.event_logs.get(lobby_id);
+        let oldest_seq = log
+            .and_then(|log| log.events.front())
+            .map_or(last_seq + 1, |event| event.seq);
+        if since > last_seq || since + 1 < oldest_seq {
+            return self.resync(session_id, last_seq);
+        }
+        let missed: Vec<String> = log
+            .into_iter()
+            .flat_map(|log| log.events.iter())
+            .filter(|event| event.seq > since && self.is_interested(session_id, &event.room_ids))
+            .map(|event| event.json.clone())
+            .collect();
+        for event in missed {
+            self.send_msg_to_user(session_id, &event);
+        }
+    }
+
+    fn resync(&mut self, session_id: &SessionId, last_seq: u64) {
+        let resync = serde_json::to_string(&ResyncRequiredEvent {
+            event: "ResyncRequired",
+            seq: last_seq,
+        });
+        if let Ok(resync) = resync {
+            self.send_msg_to_user(session_id, &resync);
+        }
+    }
+
+    /// Sends an event about the given rooms to every session of the lobby interested in one of
+    /// them, events without rooms go to everybody
+    fn send_msg_to_rooms(&mut self, lobby_id: &LobbyId, room_ids: &[RoomId], msg: &str) {
         let Some(lobby) = self.lobbies.get(lobby_id) else {
-            warn!("Lobby {} not found", lobby_id);
+            debug!("Lobby {} not found", lobby_id);
             return;
         };
-        for session_id in lobby {
-            self.send_msg_to_user(session_id, msg);
+        let recipients: Vec<SessionId> = lobby
+            .iter()
+            .filter(|session_id| self.is_interested(session_id, room_ids))
+            .copied()
+            .collect();
+        for session_id in recipients {
+            self.send_msg_to_user(&session_id, msg);
         }
     }
 
-    fn send_msg_to_user(&self, session_id: &SessionId, msg: &str) {
-        match self.sessions.get(session_id) {
-            Some(sender) => sender
-                .send(msg.to_string())
-                .expect("Message should be sent."),
-            None => warn!("Can't find socket recipient: {}", session_id),
+    fn is_interested(&self, session_id: &SessionId, room_ids: &[RoomId]) -> bool {
+        room_ids.is_empty()
+            || self
+                .subscriptions
+                .get(session_id)
+                .is_none_or(|subscriptions| subscriptions.contains_any(room_ids))
+    }
+
+    /// Sends the notification to its session, a detached session gets it on resume
+    fn notify_session(&mut self, msg: &SystemNotification) {
+        let Ok(msg_json) = msg.to_output_json_string() else {
+            warn!("Can't parse chat message event to json");
+            return;
+        };
+        match self.detached.get_mut(&msg.session_id) {
+            Some(detached) => {
+                if detached.pending.len() >= self.cfg.session_queue_size {
+                    detached.pending.remove(0);
+                }
+                detached.pending.push(msg_json);
+            }
+            None => self.send_msg_to_user(&msg.session_id, &msg_json),
+        }
+    }
+
+    /// Queues the message for the connection of the session. A full queue drops its oldest
+    /// message or closes the connection, depending on the slow consumer policy. Closed
+    /// connections are removed, the rest of the session is cleaned up by their `Disconnect`.
+    fn send_msg_to_user(&mut self, session_id: &SessionId, msg: &str) {
+        let Some(connection) = self.sessions.get(session_id) else {
+            debug!("Can't find socket recipient: {}", session_id);
+            return;
+        };
+        let queue_full = connection.sender.len() >= self.cfg.session_queue_size;
+        if queue_full && self.cfg.slow_consumer_policy == SlowConsumerPolicy::Disconnect {
+            warn!("Session {} can't keep up, closing its connection", session_id);
+            self.sessions.remove(session_id);
+            return;
+        }
+        if connection.sender.send(msg.to_string()).is_err() {
+            warn!("Socket of session {} already closed", session_id);
+            self.sessions.remove(session_id);
         }
     }
 }
 
 impl Actor for NotifyServer {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.broker = Broker::start(&self.cfg.broker, ctx.address());
+        self.webhooks = Webhooks::start(&self.cfg.webhooks);
+    }
 }
 
 /// Handler for connect message.
 impl Handler<Connect> for NotifyServer {
-    type Result = ();
+    type Result = MessageResult<Connect>;
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
+        let resumed = self.resume(&msg);
+        let joined = resumed.is_none();
+        let (session_id, pending) = resumed.unwrap_or_else(|| (Uuid::new_v4(), Vec::new()));
+        if let Some(display_name) = msg.display_name.clone() {
+            self.display_names.insert(session_id, display_name);
+        }
+
         // create a room if necessary, and then add the id to it
         self.lobbies
             .entry(msg.lobby_id)
-            .or_insert_with(HashSet::new)
-            .insert(msg.session_id);
+            .or_default()
+            .insert(session_id);
 
         debug!("Lobbies: {:?}", self.lobbies);
 
-        // store the address
-        self.sessions.insert(msg.session_id, msg.sender);
+        if let Some(room_id) = msg.room_id {
+            self.subscriptions
+                .entry(session_id)
+                .or_default()
+                .subscribe(room_id);
+        }
+
+        // store the address, a replaced connection ends when its sender is dropped
+        let connection_id = self.next_connection_id;
+        self.next_connection_id += 1;
+        self.sessions.insert(
+            session_id,
+            Connection {
+                id: connection_id,
+                sender: msg.sender,
+            },
+        );
+
+        let last_seq = self.last_seq(&msg.lobby_id);
+        let connected = serde_json::to_string(&ConnectEvent {
+            event: "Connected",
+            seq: last_seq,
+            session_id,
+            session_token: &sign_session_id(&self.cfg, session_id),
+        });
+        match connected {
+            Ok(connected) => self.send_msg_to_user(&session_id, &connected),
+            Err(_) => warn!("Can't parse connected event to json"),
+        }
+        let presence = serde_json::to_string(&PresenceEvent {
+            event: "Presence",
+            members: self.members(&msg.lobby_id),
+        });
+        match presence {
+            Ok(presence) => self.send_msg_to_user(&session_id, &presence),
+            Err(_) => warn!("Can't parse presence event to json"),
+        }
+        if joined {
+            let user_joined = serde_json::to_string(&UserJoinedEvent {
+                event: "UserJoined",
+                session_id,
+                display_name: msg.display_name.as_deref(),
+            });
+            match user_joined {
+                Ok(user_joined) => {
+                    self.send_msg_to_others(&msg.lobby_id, &session_id, &user_joined)
+                }
+                Err(_) => warn!("Can't parse user joined event to json"),
+            }
+        }
+        for pending_msg in pending {
+            self.send_msg_to_user(&session_id, &pending_msg);
+        }
+        // seq is counted per instance, with a shared broker a session unknown here may have
+        // counted on another instance
+        let since = match joined {
+            true => msg.since,
+            false => msg.since.or_else(|| self.acked_seqs.get(&session_id).copied()),
+        };
+        match since {
+            Some(_) if joined && self.broker.is_shared() => self.resync(&session_id, last_seq),
+            Some(since) => self.replay(&session_id, &msg.lobby_id, since),
+            None => {}
+        }
+        MessageResult((session_id, connection_id))
     }
 }
 
@@ -71,18 +465,29 @@ impl Handler<Connect> for NotifyServer {
 impl Handler<Disconnect> for NotifyServer {
     type Result = ();
 
-    fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        // Remove session from sessions map
-        if self.sessions.remove(&msg.session_id).is_none() {
-            warn!("Session id to delete not in sessions: {}", msg.session_id);
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Context<Self>) {
+        // The session continues with another connection
+        if self
+            .sessions
+            .get(&msg.session_id)
+            .is_some_and(|connection| connection.id != msg.connection_id)
+        {
+            return;
         }
 
+        // Remove session from sessions map, it's already gone if its connection was closed
+        let subscriptions = self
+            .subscriptions
+            .remove(&msg.session_id)
+            .unwrap_or_default();
+        self.sessions.remove(&msg.session_id);
+
         // Remove session id from lobby
         let Some(lobby_id) = self
             .lobbies
             .iter_mut()
             .find(|lobby| lobby.1.contains(&msg.session_id))
-            .and_then(|lobby| lobby.1.remove(&msg.session_id).then(|| *lobby.0))
+            .and_then(|lobby| lobby.1.remove(&msg.session_id).then_some(*lobby.0))
         else {
             warn!("Session id to delete not in lobbies: {}", msg.session_id);
             return;
@@ -97,6 +502,33 @@ impl Handler<Disconnect> for NotifyServer {
         {
             self.lobbies.remove(&lobby_id);
         }
+
+        // Keep the session for a reconnect of the client
+        let grace_period = Duration::from_secs(self.cfg.session_grace_period_sec);
+        if grace_period.is_zero() {
+            self.forget(msg.session_id, &lobby_id);
+            return;
+        }
+        let since = Instant::now();
+        self.detached.insert(
+            msg.session_id,
+            DetachedSession {
+                lobby_id,
+                subscriptions,
+                since,
+                pending: Vec::new(),
+            },
+        );
+        ctx.run_later(grace_period, move |server, _| {
+            if server
+                .detached
+                .get(&msg.session_id)
+                .is_some_and(|detached| detached.since == since)
+            {
+                server.detached.remove(&msg.session_id);
+                server.forget(msg.session_id, &lobby_id);
+            }
+        });
     }
 }
 
@@ -104,11 +536,8 @@ impl Handler<ImageUploaded> for NotifyServer {
     type Result = ();
 
     fn handle(&mut self, msg: ImageUploaded, _: &mut Context<Self>) -> Self::Result {
-        let Ok(msg_json) = msg.to_output_json_string() else {
-            warn!("Can't parse image uploaded event to json");
-            return;
-        };
-        self.send_msg_to_lobby(&msg.lobby_id, &msg_json);
+        self.emit(&msg);
+        self.broker.publish(BusEvent::ImageUploaded(msg));
     }
 }
 
@@ -116,11 +545,17 @@ impl Handler<ImageDeleted> for NotifyServer {
     type Result = ();
 
     fn handle(&mut self, msg: ImageDeleted, _: &mut Context<Self>) -> Self::Result {
-        let Ok(msg_json) = msg.to_output_json_string() else {
-            warn!("Can't parse image deleted event to json");
-            return;
-        };
-        self.send_msg_to_lobby(&msg.lobby_id, &msg_json);
+        self.emit(&msg);
+        self.broker.publish(BusEvent::ImageDeleted(msg));
+    }
+}
+
+impl Handler<ImagesDeleted> for NotifyServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ImagesDeleted, _: &mut Context<Self>) -> Self::Result {
+        self.emit(&msg);
+        self.broker.publish(BusEvent::ImagesDeleted(msg));
     }
 }
 
@@ -128,11 +563,8 @@ impl Handler<RoomDeleted> for NotifyServer {
     type Result = ();
 
     fn handle(&mut self, msg: RoomDeleted, _: &mut Context<Self>) -> Self::Result {
-        let Ok(msg_json) = msg.to_output_json_string() else {
-            warn!("Can't parse room deleted event to json");
-            return;
-        };
-        self.send_msg_to_lobby(&msg.lobby_id, &msg_json);
+        self.emit(&msg);
+        self.broker.publish(BusEvent::RoomDeleted(msg));
     }
 }
 
@@ -140,11 +572,9 @@ impl Handler<LobbyDeleted> for NotifyServer {
     type Result = ();
 
     fn handle(&mut self, msg: LobbyDeleted, _: &mut Context<Self>) -> Self::Result {
-        let Ok(msg_json) = msg.to_output_json_string() else {
-            warn!("Can't parse lobby deleted event to json");
-            return;
-        };
-        self.send_msg_to_lobby(&msg.lobby_id, &msg_json);
+        self.emit(&msg);
+        self.drop_log(&msg.lobby_id);
+        self.broker.publish(BusEvent::LobbyDeleted(msg));
     }
 }
 
@@ -152,11 +582,8 @@ impl Handler<ChatMessage> for NotifyServer {
     type Result = ();
 
     fn handle(&mut self, msg: ChatMessage, _: &mut Context<Self>) -> Self::Result {
-        let Ok(msg_json) = msg.to_output_json_string() else {
-            warn!("Can't parse chat message event to json");
-            return;
-        };
-        self.send_msg_to_lobby(&msg.lobby_id, &msg_json);
+        self.emit(&msg);
+        self.broker.publish(BusEvent::ChatMessage(msg));
     }
 }
 
@@ -164,10 +591,121 @@ impl Handler<SystemNotification> for NotifyServer {
     type Result = ();
 
     fn handle(&mut self, msg: SystemNotification, _: &mut Context<Self>) -> Self::Result {
+        // the session might be connected to another instance
+        let session_id = &msg.session_id;
+        if self.sessions.contains_key(session_id) || self.detached.contains_key(session_id) {
+            self.notify_session(&msg);
+        } else {
+            self.broker.publish(BusEvent::SystemNotification(msg));
+        }
+    }
+}
+
+/// Events of the other instances, only delivered to the sessions of this one
+impl Handler<BusEvent> for NotifyServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: BusEvent, _: &mut Context<Self>) -> Self::Result {
+        // only the instance an event comes from posts it to the webhooks
+        let _ = match msg {
+            BusEvent::ImageUploaded(event) => self.publish(&event),
+            BusEvent::ImageDeleted(event) => self.publish(&event),
+            BusEvent::ImagesDeleted(event) => self.publish(&event),
+            BusEvent::RoomDeleted(event) => self.publish(&event),
+            BusEvent::LobbyDeleted(event) => {
+                let published = self.publish(&event);
+                self.drop_log(&event.lobby_id);
+                published
+            }
+            BusEvent::ChatMessage(event) => self.publish(&event),
+            BusEvent::SystemNotification(event) => {
+                self.notify_session(&event);
+                None
+            }
+        };
+    }
+}
+
+impl Handler<Subscribe> for NotifyServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Context<Self>) -> Self::Result {
+        self.subscriptions
+            .entry(msg.session_id)
+            .or_default()
+            .subscribe(msg.room_id);
+    }
+}
+
+impl Handler<Ack> for NotifyServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Ack, _: &mut Context<Self>) -> Self::Result {
+        // only connected sessions, a late ack mustn't keep a forgotten session around
+        if self.sessions.contains_key(&msg.session_id) {
+            let acked = self.acked_seqs.entry(msg.session_id).or_default();
+            *acked = (*acked).max(msg.seq);
+        }
+    }
+}
+
+impl Handler<Unsubscribe> for NotifyServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Unsubscribe, _: &mut Context<Self>) -> Self::Result {
+        if let Some(subscriptions) = self.subscriptions.get_mut(&msg.session_id) {
+            subscriptions.unsubscribe(msg.room_id);
+        }
+    }
+}
+
+impl Handler<Typing> for NotifyServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Typing, _: &mut Context<Self>) -> Self::Result {
         let Ok(msg_json) = msg.to_output_json_string() else {
-            warn!("Can't parse chat message event to json");
+            warn!("Can't parse typing event to json");
             return;
         };
-        self.send_msg_to_user(&msg.session_id, &msg_json);
+        let Some(lobby) = self.lobbies.get(&msg.lobby_id) else {
+            return;
+        };
+        let room_ids: Vec<RoomId> = msg.room_id.into_iter().collect();
+        let recipients: Vec<SessionId> = lobby
+            .iter()
+            .filter(|id| **id != msg.session_id && self.is_interested(id, &room_ids))
+            .copied()
+            .collect();
+        for session_id in recipients {
+            self.send_msg_to_user(&session_id, &msg_json);
+        }
+    }
+}
+
+impl Handler<GetPresence> for NotifyServer {
+    type Result = MessageResult<GetPresence>;
+
+    fn handle(&mut self, msg: GetPresence, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.members(&msg.lobby_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsubscribing_the_last_room_keeps_only_lobby_events() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.unsubscribe(1);
+        assert!(subscriptions.contains_any(&[1]));
+
+        subscriptions.subscribe(1);
+        assert!(subscriptions.contains_any(&[1]));
+        assert!(!subscriptions.contains_any(&[2]));
+
+        subscriptions.unsubscribe(1);
+        assert!(!subscriptions.contains_any(&[1]));
+        assert!(!subscriptions.contains_any(&[2]));
     }
 }
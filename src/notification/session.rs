@@ -0,0 +1,97 @@
+use crate::{config::NotificationCfg, SessionId};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Session token handed to the client, valid for `session_token_ttl_sec`. Format:
+/// `payload.base64url(hmac-sha256(secret, payload))` with the payload
+/// `session_id.issued_at.expires_at`, the times in unix seconds
+pub fn sign_session_id(cfg: &NotificationCfg, session_id: SessionId) -> String {
+    sign_session_id_at(cfg, session_id, now_sec())
+}
+
+fn sign_session_id_at(cfg: &NotificationCfg, session_id: SessionId, issued_at: u64) -> String {
+    let expires_at = issued_at.saturating_add(cfg.session_token_ttl_sec);
+    let payload = format!("{session_id}.{issued_at}.{expires_at}");
+    let signature = URL_SAFE_NO_PAD.encode(mac(cfg, &payload).finalize().into_bytes());
+    format!("{payload}.{signature}")
+}
+
+/// Session id of an unexpired token signed by this server, `None` for unsigned, forged or
+/// expired ones
+pub fn verify_session_token(cfg: &NotificationCfg, token: &str) -> Option<SessionId> {
+    verify_session_token_at(cfg, token, now_sec())
+}
+
+fn verify_session_token_at(cfg: &NotificationCfg, token: &str, now: u64) -> Option<SessionId> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    mac(cfg, payload).verify_slice(&signature).ok()?;
+
+    let mut parts = payload.split('.');
+    let (Some(session_id), Some(issued_at), Some(expires_at), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    let issued_at: u64 = issued_at.parse().ok()?;
+    let expires_at: u64 = expires_at.parse().ok()?;
+    // a shorter ttl applies to tokens issued before it was configured too
+    let expires_at = expires_at.min(issued_at.saturating_add(cfg.session_token_ttl_sec));
+    if now >= expires_at {
+        return None;
+    }
+    SessionId::parse_str(session_id).ok()
+}
+
+fn mac(cfg: &NotificationCfg, payload: &str) -> HmacSha256 {
+    // unwrap: hmac accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(cfg.session_secret.as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+    mac
+}
+
+fn now_sec() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_tokens_until_they_expire() {
+        let cfg = NotificationCfg::default();
+        let session_id = SessionId::new_v4();
+        let token = sign_session_id_at(&cfg, session_id, 1000);
+        let expires_at = 1000 + cfg.session_token_ttl_sec;
+
+        assert_eq!(verify_session_token_at(&cfg, &token, 1000), Some(session_id));
+        assert_eq!(verify_session_token_at(&cfg, &token, expires_at - 1), Some(session_id));
+        assert_eq!(verify_session_token_at(&cfg, &token, expires_at), None);
+    }
+
+    #[test]
+    fn rejects_changed_tokens() {
+        let cfg = NotificationCfg::default();
+        let session_id = SessionId::new_v4();
+        let token = sign_session_id_at(&cfg, session_id, 1000);
+        let expires_at = 1000 + cfg.session_token_ttl_sec;
+
+        let extended = token.replace(&format!(".{expires_at}."), &format!(".{}.", expires_at * 2));
+        assert_eq!(verify_session_token_at(&cfg, &extended, expires_at), None);
+        let other_secret = NotificationCfg::default();
+        assert_eq!(verify_session_token_at(&other_secret, &token, 1000), None);
+        // tokens without issue time signed before expiry was added
+        let (id, _) = token.split_once('.').unwrap();
+        let mac = mac(&cfg, id).finalize().into_bytes();
+        let legacy = format!("{id}.{}", URL_SAFE_NO_PAD.encode(mac));
+        assert_eq!(verify_session_token_at(&cfg, &legacy, 1000), None);
+    }
+}
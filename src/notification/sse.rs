@@ -0,0 +1,162 @@
+use super::{
+    connect_msg,
+    internal_messages::Disconnect,
+    server::{ConnectionId, NotifyServer},
+    session::sign_session_id,
+    session_cookie, HEARTBEAT_INTERVAL,
+};
+use crate::{
+    config::ServerConfig,
+    permission::{check, rate_limit::RateLimited, Action},
+    LobbyId, SessionId,
+};
+use actix::prelude::*;
+use actix_web::{
+    error::ErrorServiceUnavailable,
+    get,
+    http::header::{self, CacheControl, CacheDirective},
+    web::{Bytes, Data, Path},
+    Error, HttpRequest, HttpResponse,
+};
+use futures_util::{
+    future::{select, Either},
+    stream,
+};
+use serde::Deserialize;
+use std::{pin::pin, sync::Arc};
+use tokio::{
+    sync::broadcast::{error::RecvError, Receiver},
+    time::{interval, Interval},
+};
+
+/// Sequence number of an event, to be sent as the id of its SSE message
+#[derive(Deserialize)]
+struct EventSeq {
+    event: Option<String>,
+    seq: Option<u64>,
+}
+
+/// Server-Sent Events stream of a lobby for clients that can't use websockets. The events are
+/// the same as on the websocket, `Last-Event-ID` replays the missed ones like `since`.
+#[get("/events/{lobby_id}", wrap = "RateLimited(Action::ConnectNotifications)")]
+pub async fn start_event_stream(
+    req: HttpRequest,
+    path: Path<(LobbyId,)>,
+    notify_server: Data<Addr<NotifyServer>>,
+    cfg: Data<ServerConfig>,
+) -> Result<HttpResponse, Error> {
+    let lobby_id = path.0;
+
+    // check permission
+    if let Some(err) =
+        check(&cfg.permissions, Action::ConnectNotifications, &req, &(lobby_id,)).await
+    {
+        return Ok(err);
+    }
+
+    let (mut connect_msg, conn_rx) = connect_msg(&req, &cfg, lobby_id, None).await;
+
+    // sent by the browser when it reconnects on its own
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.trim().parse().ok());
+    if last_event_id.is_some() {
+        connect_msg.since = last_event_id;
+    }
+    let since = connect_msg.since;
+
+    let (session_id, connection_id) = notify_server
+        .send(connect_msg)
+        .await
+        .map_err(ErrorServiceUnavailable)?;
+    let token = sign_session_id(&cfg.notifications, session_id);
+    let events = EventStream {
+        conn_rx,
+        heartbeat: interval(HEARTBEAT_INTERVAL),
+        notify_server: notify_server.into_inner(),
+        session_id,
+        connection_id,
+        since,
+    };
+    let body = stream::unfold(events, |mut events| async move {
+        let frame = events.next_frame().await?;
+        Some((Ok::<_, Error>(Bytes::from(frame)), events))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        // keeps proxies like nginx from holding the events back
+        .insert_header(("X-Accel-Buffering", "no"))
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .cookie(session_cookie(&cfg.notifications, &token))
+        .streaming(body))
+}
+
+/// Connection of an SSE client, the session is disconnected when the response is dropped
+struct EventStream {
+    conn_rx: Receiver<String>,
+    heartbeat: Interval,
+    notify_server: Arc<Addr<NotifyServer>>,
+    session_id: SessionId,
+    connection_id: ConnectionId,
+
+    // event the client resumes after, the replayed events follow `Connected`
+    since: Option<u64>,
+}
+
+impl EventStream {
+    /// Next event or heartbeat comment, `None` once the notify server closed the connection
+    async fn next_frame(&mut self) -> Option<String> {
+        loop {
+            // `None` when the heartbeat is due
+            let received = {
+                let msg_rx = pin!(self.conn_rx.recv());
+                let tick = pin!(self.heartbeat.tick());
+                match select(msg_rx, tick).await {
+                    Either::Left((received, _)) => Some(received),
+                    Either::Right(_) => None,
+                }
+            };
+            match received {
+                Some(Ok(msg)) => return Some(self.sse_message(&msg)),
+
+                // the client is too slow, the oldest messages were dropped
+                Some(Err(RecvError::Lagged(skipped))) => {
+                    log::warn!("event stream client too slow; {skipped} messages dropped");
+                }
+
+                // the session continues elsewhere or was too slow
+                Some(Err(RecvError::Closed)) => return None,
+
+                // keeps proxies from closing the idle connection, and detects closed clients
+                None => return Some(String::from(": heartbeat\n\n")),
+            }
+        }
+    }
+
+    /// SSE message of an event, numbered events get their `seq` as id. `Connected` keeps the
+    /// id the client resumes after, so a connection lost during the replay resumes there again.
+    fn sse_message(&self, json: &str) -> String {
+        let id = match serde_json::from_str::<EventSeq>(json) {
+            Ok(EventSeq {
+                event,
+                seq: Some(seq),
+            }) if event.as_deref() == Some("Connected") => Some(self.since.unwrap_or(seq)),
+            Ok(EventSeq { seq, .. }) => seq,
+            Err(_) => None,
+        };
+        let id = id.map(|id| format!("id: {id}\n")).unwrap_or_default();
+        let data: String = json.lines().map(|line| format!("data: {line}\n")).collect();
+        format!("{id}{data}\n")
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.notify_server
+            .do_send(Disconnect::new(self.session_id, self.connection_id));
+    }
+}
@@ -0,0 +1,471 @@
+use crate::{LobbyId, config::WebhooksCfg};
+use futures_util::{
+    StreamExt as _,
+    future::{Either, pending, select},
+    stream::FuturesUnordered,
+};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{
+    collections::HashSet,
+    fs,
+    path::Path,
+    pin::pin,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::sleep,
+};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// delivery with the HTTP status on success
+type Attempted = (Delivery, Result<u16, (Option<u16>, String)>);
+
+// wakes the delivery task from time to time even if nothing is queued
+const IDLE_WAIT: Duration = Duration::from_secs(60 * 60);
+
+// changes of the retry queue are collected this long before the queue file is written
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Posts the lobby events of this instance to the subscribed webhooks. Deliveries run in their
+/// own task, failed ones are retried with backoff.
+#[derive(Default)]
+pub struct Webhooks {
+    cfg: WebhooksCfg,
+    sender: Option<mpsc::Sender<Delivery>>,
+}
+
+/// Event waiting to be posted to one webhook
+#[derive(Serialize, Deserialize, Clone)]
+struct Delivery {
+    id: Uuid,
+
+    // index in `subscriptions`, several subscriptions can share a url with other secrets
+    #[serde(default)]
+    subscription: usize,
+    url: String,
+    lobby_id: LobbyId,
+    event: String,
+    body: String,
+
+    // failed attempts so far
+    attempts: u32,
+    next_attempt_ms: u64,
+}
+
+#[derive(Serialize)]
+struct DeliveryRecord<'a> {
+    time_ms: u64,
+    delivery_id: Uuid,
+    url: &'a str,
+    lobby_id: LobbyId,
+    event: &'a str,
+    attempt: u32,
+    status: Option<u16>,
+    error: Option<&'a str>,
+    outcome: DeliveryOutcome,
+}
+
+#[derive(Serialize)]
+enum DeliveryOutcome {
+    Delivered,
+    RetryIn { sec: u64 },
+    GaveUp,
+    Dropped,
+}
+
+impl Webhooks {
+    /// Starts the delivery task, nothing is started without subscriptions
+    pub fn start(cfg: &WebhooksCfg) -> Self {
+        if cfg.subscriptions.is_empty() {
+            return Self::default();
+        }
+        // the delivery task takes them over right away, unless its queue is full
+        let (sender, receiver) = mpsc::channel(cfg.max_queue_size.max(1));
+        actix::spawn(deliver_loop(cfg.clone(), receiver));
+        Self {
+            cfg: cfg.clone(),
+            sender: Some(sender),
+        }
+    }
+
+    /// Queues the websocket event `json` for every webhook subscribed to the lobby. The body is
+    /// the event with its `lobby_id` and the `delivery_id`, which stays the same on retries.
+    pub fn post(&self, lobby_id: LobbyId, json: &str) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let Ok(Value::Object(mut event)) = serde_json::from_str(json) else {
+            return warn!("Can't parse event for webhooks");
+        };
+        let event_name = event
+            .get("event")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        event.insert(
+            String::from("lobby_id"),
+            Value::String(lobby_id.to_string()),
+        );
+        let subscriptions = self.cfg.subscriptions.iter().enumerate().filter(|(_, sub)| {
+            sub.lobbies
+                .as_ref()
+                .is_none_or(|lobbies| lobbies.contains(&lobby_id))
+        });
+        for (idx, subscription) in subscriptions {
+            let id = Uuid::new_v4();
+            event.insert(String::from("delivery_id"), Value::String(id.to_string()));
+            let Ok(body) = serde_json::to_string(&event) else {
+                continue;
+            };
+            let delivery = Delivery {
+                id,
+                subscription: idx,
+                url: subscription.url.clone(),
+                lobby_id,
+                event: event_name.clone(),
+                body,
+                attempts: 0,
+                next_attempt_ms: now_ms(),
+            };
+            match sender.try_send(delivery) {
+                Ok(()) => {}
+                Err(TrySendError::Full(delivery)) => {
+                    warn!("Webhook queue full, delivery to {} dropped", delivery.url);
+                    log(&self.cfg, &delivery, None, None, DeliveryOutcome::Dropped);
+                }
+                Err(TrySendError::Closed(_)) => {
+                    warn!("Webhook delivery task stopped, event not posted");
+                }
+            }
+        }
+    }
+}
+
+async fn deliver_loop(cfg: WebhooksCfg, mut receiver: mpsc::Receiver<Delivery>) {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(cfg.timeout_sec))
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => return warn!("Can't create webhook client: {err}"),
+    };
+    let max_concurrency = cfg.max_concurrency.max(1);
+    let mut queue = RetryQueue::load(&cfg);
+    let mut in_flight = FuturesUnordered::new();
+    loop {
+        let free = max_concurrency - in_flight.len();
+        for delivery in queue.take_due(now_ms(), free) {
+            in_flight.push(attempt(&client, &cfg, delivery));
+        }
+        queue.save_if_due(&cfg);
+
+        // a due retry has to wait for a free slot anyway
+        let wait = match in_flight.len() < max_concurrency {
+            true => queue.next_attempt_ms().map_or(IDLE_WAIT, |next| {
+                Duration::from_millis(next.saturating_sub(now_ms()))
+            }),
+            false => IDLE_WAIT,
+        };
+        let wait = queue.next_save_in().map_or(wait, |save_in| wait.min(save_in));
+
+        // new deliveries are still received while attempts are running
+        let wake = {
+            let recv = pin!(receiver.recv());
+            let attempted = pin!(async {
+                match in_flight.is_empty() {
+                    true => pending().await,
+                    false => in_flight.next().await,
+                }
+            });
+            let wait = pin!(sleep(wait));
+            match select(select(recv, attempted), wait).await {
+                Either::Left((Either::Left((received, _)), _)) => Wake::Received(received),
+                Either::Left((Either::Right((attempted, _)), _)) => Wake::Attempted(attempted),
+                Either::Right(_) => Wake::Timeout,
+            }
+        };
+        match wake {
+            Wake::Received(Some(delivery)) => {
+                queue.push(&cfg, delivery);
+                while let Ok(delivery) = receiver.try_recv() {
+                    queue.push(&cfg, delivery);
+                }
+            }
+
+            // the notify server stopped, the queue file keeps the rest, running attempts included
+            Wake::Received(None) => {
+                queue.save(&cfg);
+                break;
+            }
+            Wake::Attempted(Some((delivery, res))) => queue.finish(&cfg, delivery, res),
+            Wake::Attempted(None) | Wake::Timeout => {}
+        }
+    }
+}
+
+enum Wake {
+    Received(Option<Delivery>),
+    Attempted(Option<Attempted>),
+    Timeout,
+}
+
+/// Posts the delivery, answered with the HTTP status on success
+async fn attempt(client: &reqwest::Client, cfg: &WebhooksCfg, delivery: Delivery) -> Attempted {
+    let subscription = cfg
+        .subscriptions
+        .get(delivery.subscription)
+        .filter(|sub| sub.url == delivery.url);
+    let Some(subscription) = subscription else {
+        return (
+            delivery,
+            Err((None, String::from("Webhook not subscribed anymore"))),
+        );
+    };
+    let timestamp = now_ms() / 1000;
+    let res = client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Wim-Event", &delivery.event)
+        .header("X-Wim-Delivery", delivery.id.to_string())
+        .header("X-Wim-Timestamp", timestamp.to_string())
+        .header(
+            "X-Wim-Signature",
+            sign(&subscription.secret, timestamp, &delivery.body),
+        )
+        .body(delivery.body.clone())
+        .send()
+        .await;
+    let res = match res {
+        Ok(res) if res.status().is_success() => Ok(res.status().as_u16()),
+        Ok(res) => Err((
+            Some(res.status().as_u16()),
+            format!("Webhook answered {}", res.status()),
+        )),
+        Err(err) => Err((None, err.to_string())),
+    };
+    (delivery, res)
+}
+
+/// `sha256=` and the hex encoded HMAC-SHA256 of `{timestamp}.{body}`, so a captured request can't
+/// be replayed with a new timestamp
+fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    // unwrap: hmac accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("sha256={signature}")
+}
+
+/// Deliveries waiting for their next attempt, written to the queue file `SAVE_DELAY` after the
+/// first unsaved change
+#[derive(Default)]
+struct RetryQueue {
+    deliveries: Vec<Delivery>,
+
+    // deliveries posted right now, they stay queued until their attempt is finished
+    in_flight: HashSet<Uuid>,
+    changed_at: Option<Instant>,
+}
+
+impl RetryQueue {
+    /// Queue left by the last run, without deliveries of removed subscriptions. Moved
+    /// subscriptions are found by their url.
+    fn load(cfg: &WebhooksCfg) -> Self {
+        let Some(path) = cfg
+            .queue_path
+            .as_ref()
+            .filter(|path| Path::new(path).exists())
+        else {
+            return Self::default();
+        };
+        let deliveries: Vec<Delivery> = match fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|err| err.to_string()))
+        {
+            Ok(deliveries) => deliveries,
+            Err(err) => {
+                warn!("Can't read webhook queue {path}: {err}");
+                return Self::default();
+            }
+        };
+        let deliveries: Vec<Delivery> = deliveries
+            .into_iter()
+            .filter_map(|mut delivery| {
+                let subscription = cfg.subscriptions.get(delivery.subscription);
+                if subscription.is_none_or(|sub| sub.url != delivery.url) {
+                    delivery.subscription = cfg
+                        .subscriptions
+                        .iter()
+                        .position(|sub| sub.url == delivery.url)?;
+                }
+                Some(delivery)
+            })
+            .collect();
+        info!("{} webhook deliveries restored", deliveries.len());
+        Self {
+            deliveries,
+            ..Default::default()
+        }
+    }
+
+    fn changed(&mut self) {
+        self.changed_at.get_or_insert_with(Instant::now);
+    }
+
+    /// Time until the unsaved changes are written
+    fn next_save_in(&self) -> Option<Duration> {
+        self.changed_at
+            .map(|changed_at| SAVE_DELAY.saturating_sub(changed_at.elapsed()))
+    }
+
+    fn save_if_due(&mut self, cfg: &WebhooksCfg) {
+        if self.next_save_in().is_some_and(|save_in| save_in.is_zero()) {
+            self.save(cfg);
+        }
+    }
+
+    fn save(&mut self, cfg: &WebhooksCfg) {
+        if self.changed_at.take().is_none() {
+            return;
+        }
+        let Some(path) = &cfg.queue_path else {
+            return;
+        };
+        let res = serde_json::to_string(&self.deliveries)
+            .map_err(|err| err.to_string())
+            .and_then(|json| {
+                if let Some(dir) = Path::new(path).parent() {
+                    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+                }
+                // replaced at once, so a crash can't leave half a file
+                let tmp_path = format!("{path}.tmp");
+                fs::write(&tmp_path, json).map_err(|err| err.to_string())?;
+                fs::rename(&tmp_path, path).map_err(|err| err.to_string())
+            });
+        if let Err(err) = res {
+            warn!("Can't write webhook queue {path}: {err}");
+        }
+    }
+
+    fn push(&mut self, cfg: &WebhooksCfg, delivery: Delivery) {
+        if self.deliveries.len() >= cfg.max_queue_size {
+            warn!("Webhook queue full, delivery to {} dropped", delivery.url);
+            log(cfg, &delivery, None, None, DeliveryOutcome::Dropped);
+            return;
+        }
+        self.deliveries.push(delivery);
+        self.changed();
+    }
+
+    fn waiting(&self) -> impl Iterator<Item = &Delivery> {
+        self.deliveries
+            .iter()
+            .filter(|delivery| !self.in_flight.contains(&delivery.id))
+    }
+
+    /// Up to `max` due deliveries, they are in flight until finished
+    fn take_due(&mut self, now_ms: u64, max: usize) -> Vec<Delivery> {
+        let due: Vec<Delivery> = self
+            .waiting()
+            .filter(|delivery| delivery.next_attempt_ms <= now_ms)
+            .take(max)
+            .cloned()
+            .collect();
+        self.in_flight.extend(due.iter().map(|delivery| delivery.id));
+        due
+    }
+
+    fn next_attempt_ms(&self) -> Option<u64> {
+        self.waiting().map(|delivery| delivery.next_attempt_ms).min()
+    }
+
+    /// Logs the attempt and queues the delivery again if it failed and has attempts left
+    fn finish(
+        &mut self,
+        cfg: &WebhooksCfg,
+        mut delivery: Delivery,
+        res: Result<u16, (Option<u16>, String)>,
+    ) {
+        self.in_flight.remove(&delivery.id);
+        self.deliveries.retain(|queued| queued.id != delivery.id);
+        self.changed();
+        delivery.attempts += 1;
+        let (status, error) = match res {
+            Ok(status) => {
+                return log(
+                    cfg,
+                    &delivery,
+                    Some(status),
+                    None,
+                    DeliveryOutcome::Delivered,
+                );
+            }
+            Err(failure) => failure,
+        };
+        if delivery.attempts >= cfg.max_attempts {
+            warn!("Webhook delivery to {} given up: {error}", delivery.url);
+            return log(
+                cfg,
+                &delivery,
+                status,
+                Some(&error),
+                DeliveryOutcome::GaveUp,
+            );
+        }
+        let backoff = cfg
+            .initial_backoff_sec
+            .saturating_mul(1 << (delivery.attempts - 1).min(31))
+            .min(cfg.max_backoff_sec);
+        delivery.next_attempt_ms = now_ms() + backoff * 1000;
+        log(
+            cfg,
+            &delivery,
+            status,
+            Some(&error),
+            DeliveryOutcome::RetryIn { sec: backoff },
+        );
+        self.push(cfg, delivery);
+    }
+}
+
+fn log(
+    cfg: &WebhooksCfg,
+    delivery: &Delivery,
+    status: Option<u16>,
+    error: Option<&str>,
+    outcome: DeliveryOutcome,
+) {
+    let Some(delivery_log) = &cfg.delivery_log else {
+        return;
+    };
+    delivery_log.append(&DeliveryRecord {
+        time_ms: now_ms(),
+        delivery_id: delivery.id,
+        url: &delivery.url,
+        lobby_id: delivery.lobby_id,
+        event: &delivery.event,
+        attempt: delivery.attempts,
+        status,
+        error,
+        outcome,
+    });
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or_default()
+}
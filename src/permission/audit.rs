@@ -0,0 +1,287 @@
+use super::{Action, Refusal, ip::client_ip};
+use crate::{
+    ImgId, LobbyId, RoomId, SessionId,
+    utils::{ParamTuple, get_session_id},
+};
+use actix_web::{HttpMessage, HttpRequest, http::header};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::Path,
+    sync::{
+        Arc, OnceLock,
+        mpsc::{self, SyncSender, TrySendError},
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// lines waiting for the writer thread, further ones are dropped while the disk can't keep up
+const QUEUE_SIZE: usize = 10000;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct AuditLogCfg {
+    // JSON lines file every permission decision is appended to
+    path: String,
+
+    // the file is rotated to `<path>.1` when it would grow above this size
+    #[serde(default = "default_max_size_byte")]
+    max_size_byte: u64,
+
+    // number of rotated files kept next to the current one
+    #[serde(default = "default_max_files")]
+    max_files: usize,
+
+    #[serde(skip)]
+    file: AuditFile,
+}
+
+fn default_max_size_byte() -> u64 {
+    1024 * 1024 * 10 // 10 MB
+}
+
+fn default_max_files() -> usize {
+    5
+}
+
+/// Result of one request to a confirmation server, collected in the request extensions
+#[derive(Serialize, Clone, Debug)]
+pub struct ConfirmationTrace {
+    pub url: String,
+    pub cached: bool,
+    pub latency_ms: Option<u128>,
+    pub allowed: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct ConfirmationTraces(Vec<ConfirmationTrace>);
+
+pub fn trace_confirmation(req: &HttpRequest, trace: ConfirmationTrace) {
+    let mut extensions = req.extensions_mut();
+    match extensions.get_mut::<ConfirmationTraces>() {
+        Some(traces) => traces.0.push(trace),
+        None => {
+            extensions.insert(ConfirmationTraces(vec![trace]));
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub enum Outcome {
+    Allowed,
+    Denied(String),
+    Failed(String),
+    RateLimited { retry_after_sec: u64 },
+}
+
+impl Outcome {
+    pub fn from_result(res: &Result<(), Refusal>) -> Self {
+        match res {
+            Ok(()) => Outcome::Allowed,
+            Err(Refusal::Denied(msg)) => Outcome::Denied(msg.clone()),
+            Err(Refusal::Failed(err)) => Outcome::Failed(err.clone()),
+        }
+    }
+
+    pub fn rate_limited(retry_after: Duration) -> Self {
+        Outcome::RateLimited {
+            retry_after_sec: retry_after.as_secs_f64().ceil() as u64,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    time_ms: u128,
+    action: Action,
+    route: &'a str,
+    lobby_id: LobbyId,
+    room_id: Option<RoomId>,
+    img_id: Option<ImgId>,
+    restriction: String,
+    confirmations: Vec<ConfirmationTrace>,
+    origin: Option<&'a str>,
+    referer: Option<&'a str>,
+    ip: Option<IpAddr>,
+    session_id: Option<SessionId>,
+    outcome: Outcome,
+}
+
+impl AuditLogCfg {
+    pub fn log<T: ParamTuple>(
+        &self,
+        action: Action,
+        req: &HttpRequest,
+        params: &T,
+        restriction: String,
+        outcome: Outcome,
+    ) {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        let scope = params.scope();
+        let confirmations = req
+            .extensions_mut()
+            .remove::<ConfirmationTraces>()
+            .map(|traces| traces.0)
+            .unwrap_or_default();
+        let record = AuditRecord {
+            time_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_millis())
+                .unwrap_or_default(),
+            action,
+            route: req.path(),
+            lobby_id: scope.lobby_id,
+            room_id: scope.room_id,
+            img_id: scope.img_id,
+            restriction,
+            confirmations,
+            origin: header(header::ORIGIN),
+            referer: header(header::REFERER),
+            ip: client_ip(req),
+            session_id: get_session_id(req),
+            outcome,
+        };
+        self.append(&record);
+    }
+
+    /// Appends one record as JSON line, also used for other logs like the webhook deliveries.
+    /// The line is written by a thread of its own, so a slow disk doesn't hold up requests.
+    pub fn append<R: Serialize>(&self, record: &R) {
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(err) => return warn!("Can't serialize log record: {err}"),
+        };
+        match self.file.sender(self).try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Log {} can't keep up, record dropped", self.path),
+            Err(TrySendError::Disconnected(_)) => warn!("Log {} writer stopped", self.path),
+        }
+    }
+}
+
+/// Queue of the writer thread, started with the first record
+#[derive(Clone, Default)]
+struct AuditFile(Arc<OnceLock<SyncSender<String>>>);
+
+impl fmt::Debug for AuditFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AuditFile")
+    }
+}
+
+impl AuditFile {
+    fn sender(&self, cfg: &AuditLogCfg) -> &SyncSender<String> {
+        self.0.get_or_init(|| {
+            let (sender, receiver) = mpsc::sync_channel::<String>(QUEUE_SIZE);
+            // without the sender, so the thread ends when the config is dropped
+            let mut writer = LogWriter {
+                path: cfg.path.clone(),
+                max_size_byte: cfg.max_size_byte,
+                max_files: cfg.max_files,
+                file: None,
+            };
+            thread::spawn(move || {
+                for line in receiver {
+                    if let Err(err) = writer.append(&line) {
+                        warn!("Can't write log {}: {err}", writer.path);
+                    }
+                }
+            });
+            sender
+        })
+    }
+}
+
+/// Open log file with its size, owned by the writer thread
+struct LogWriter {
+    path: String,
+    max_size_byte: u64,
+    max_files: usize,
+    file: Option<(File, u64)>,
+}
+
+impl LogWriter {
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        let line_len = line.len() as u64 + 1;
+        if let Some((_, size)) = self.file.as_ref()
+            && *size > 0
+            && size + line_len > self.max_size_byte
+        {
+            self.file = None;
+            rotate(&self.path, self.max_files)?;
+        }
+        if self.file.is_none() {
+            if let Some(dir) = Path::new(&self.path).parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let opened = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            let size = opened.metadata()?.len();
+            self.file = Some((opened, size));
+        }
+        let Some((file, size)) = self.file.as_mut() else {
+            return Ok(());
+        };
+        writeln!(file, "{line}")?;
+        *size += line_len;
+        Ok(())
+    }
+}
+
+/// Moves `<path>` to `<path>.1`, `<path>.1` to `<path>.2` and so on, dropping the oldest file
+fn rotate(path: &str, max_files: usize) -> io::Result<()> {
+    if max_files == 0 {
+        return fs::remove_file(path);
+    }
+    for idx in (1..max_files).rev() {
+        let from = format!("{path}.{idx}");
+        if Path::new(&from).exists() {
+            fs::rename(from, format!("{path}.{}", idx + 1))?;
+        }
+    }
+    fs::rename(path, format!("{path}.1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use uuid::Uuid;
+
+    #[test]
+    fn writes_and_rotates_in_the_background() {
+        let dir = env::temp_dir().join(format!("wim-audit-{}", Uuid::new_v4()));
+        let path = dir.join("audit.jsonl").to_string_lossy().to_string();
+        let cfg = AuditLogCfg {
+            path: path.clone(),
+            max_size_byte: 20,
+            max_files: 1,
+            file: AuditFile::default(),
+        };
+        for idx in 0..3 {
+            cfg.append(&format!("record {idx}"));
+        }
+
+        let read = |path: &str| fs::read_to_string(path).unwrap_or_default();
+        for _ in 0..100 {
+            if read(&path) == "\"record 2\"\n" {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(read(&path), "\"record 2\"\n");
+        assert_eq!(read(&format!("{path}.1")), "\"record 1\"\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
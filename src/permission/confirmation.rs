@@ -0,0 +1,378 @@
+use super::{
+    Action, Refusal,
+    audit::{ConfirmationTrace, trace_confirmation},
+    ip::client_ip,
+};
+use crate::{
+    public_messages::permission::ConfirmationResponse,
+    utils::{ParamTuple, get_session_id, http_client},
+};
+use actix_web::HttpRequest;
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum ConfirmationMethod {
+    Get,
+    Post,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum ConfirmationFormat {
+    Json,
+    Form,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ConfirmationRequest {
+    url: String,
+    method: ConfirmationMethod,
+    format: ConfirmationFormat,
+    params: HashMap<String, Value>,
+    headers: HashMap<String, String>,
+
+    // remember answers of the confirmation server
+    cache: Option<ConfirmationCacheCfg>,
+
+    // information about the caller added to the request
+    forward: Option<ForwardCfg>,
+
+    #[serde(skip)]
+    cached: ConfirmationCache,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ConfirmationCacheCfg {
+    // lifetime of a positive answer, if the server sends no Cache-Control max-age
+    ttl_sec: u64,
+
+    // lifetime of a negative answer, 0 disables negative caching
+    negative_ttl_sec: u64,
+
+    // request headers that are part of the cache key, e.g. Authorization
+    vary_headers: Vec<String>,
+
+    // request cookies that are part of the cache key
+    vary_cookies: Vec<String>,
+
+    // maximum number of cached answers
+    max_entries: usize,
+}
+
+impl Default for ConfirmationCacheCfg {
+    fn default() -> Self {
+        Self {
+            ttl_sec: 60,
+            negative_ttl_sec: 10,
+            vary_headers: Vec::new(),
+            vary_cookies: Vec::new(),
+            max_entries: 10000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ForwardCfg {
+    // incoming request headers copied into the confirmation request, e.g. Authorization, Cookie
+    headers: Vec<String>,
+
+    // param name for the websocket session id of the caller
+    session_id: Option<String>,
+
+    // param name for the ip address of the caller
+    client_ip: Option<String>,
+
+    // param name for the requested action, e.g. "delete_img"
+    action: Option<String>,
+}
+
+impl ForwardCfg {
+    fn edit_param_map(
+        &self,
+        action: Option<Action>,
+        req: &HttpRequest,
+        map: &mut HashMap<String, Value>,
+    ) {
+        let mut insert = |name: &Option<String>, value: Option<String>| {
+            if let (Some(name), Some(value)) = (name, value) {
+                map.insert(name.clone(), Value::String(value));
+            }
+        };
+        insert(
+            &self.session_id,
+            get_session_id(req).map(|id| id.to_string()),
+        );
+        insert(&self.client_ip, client_ip(req).map(|ip| ip.to_string()));
+        insert(&self.action, action.map(|action| action.as_str().to_string()));
+    }
+
+    fn header_map(&self, req: &HttpRequest) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for name in &self.headers {
+            let Ok(header_name) = HeaderName::from_bytes(name.as_bytes()) else {
+                continue;
+            };
+            for value in req.headers().get_all(name.as_str()) {
+                if let Ok(value) = HeaderValue::from_bytes(value.as_bytes()) {
+                    headers.append(header_name.clone(), value);
+                }
+            }
+        }
+        headers
+    }
+}
+
+type CacheEntries = HashMap<String, (Instant, Result<(), Refusal>)>;
+
+#[derive(Clone, Default)]
+struct ConfirmationCache(Arc<Mutex<CacheEntries>>);
+
+impl fmt::Debug for ConfirmationCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ConfirmationCache")
+    }
+}
+
+impl ConfirmationRequest {
+    pub async fn is_allowed<T: ParamTuple>(
+        &self,
+        action: Action,
+        req: &HttpRequest,
+        params: &T,
+    ) -> Result<(), Refusal> {
+        let mut conf_params = self.params.clone();
+        params.edit_param_map(&mut conf_params);
+        let mut forward_headers = HeaderMap::new();
+        if let Some(forward) = &self.forward {
+            forward.edit_param_map(Some(action), req, &mut conf_params);
+            forward_headers = forward.header_map(req);
+        }
+
+        let Some(cache_cfg) = &self.cache else {
+            return self.send_traced(req, &conf_params, forward_headers).await.0;
+        };
+
+        let key = cache_key(cache_cfg, req, &conf_params, &forward_headers);
+        if let Some(res) = self.cached.get(&key) {
+            trace_confirmation(req, self.trace(&res, None));
+            return res;
+        }
+
+        let (res, max_age) = self.send_traced(req, &conf_params, forward_headers).await;
+        let ttl = match (&res, max_age) {
+            (_, Some(CacheControl::NoStore)) => None,
+            (Ok(()), Some(CacheControl::MaxAge(max_age))) => Some(max_age),
+            (Ok(()), None) => Some(Duration::from_secs(cache_cfg.ttl_sec)),
+            (Err(_), _) if cache_cfg.negative_ttl_sec > 0 => {
+                Some(Duration::from_secs(cache_cfg.negative_ttl_sec))
+            }
+            (Err(_), _) => None,
+        };
+        if let Some(ttl) = ttl.filter(|ttl| !ttl.is_zero()) {
+            self.cached
+                .insert(key, res.clone(), ttl, cache_cfg.max_entries);
+        }
+        res
+    }
+
+    /// Display name of the caller from the `display_name` of the answer, used for presence
+    pub async fn identify<T: ParamTuple>(
+        &self,
+        req: &HttpRequest,
+        params: &T,
+    ) -> Result<Option<String>, String> {
+        let mut conf_params = self.params.clone();
+        params.edit_param_map(&mut conf_params);
+        let mut forward_headers = HeaderMap::new();
+        if let Some(forward) = &self.forward {
+            forward.edit_param_map(None, req, &mut conf_params);
+            forward_headers = forward.header_map(req);
+        }
+        let (response, _) = self.fetch(&conf_params, forward_headers).await?;
+        match response.is_allowed {
+            true => Ok(response.display_name),
+            false => Err(not_allowed_msg(response.error_msg)),
+        }
+    }
+
+    async fn send_traced(
+        &self,
+        req: &HttpRequest,
+        params: &HashMap<String, Value>,
+        forward_headers: HeaderMap,
+    ) -> (Result<(), Refusal>, Option<CacheControl>) {
+        let started = Instant::now();
+        let (res, cache_control) = self.send(params, forward_headers).await;
+        trace_confirmation(req, self.trace(&res, Some(started.elapsed())));
+        (res, cache_control)
+    }
+
+    /// Audit log entry, without latency for cached answers
+    fn trace(&self, res: &Result<(), Refusal>, latency: Option<Duration>) -> ConfirmationTrace {
+        ConfirmationTrace {
+            url: self.url.clone(),
+            cached: latency.is_none(),
+            latency_ms: latency.map(|latency| latency.as_millis()),
+            allowed: res.is_ok(),
+            error: res.as_ref().err().map(Refusal::to_string),
+        }
+    }
+
+    /// Asks the confirmation server. Failed requests are marked as not storable, so only real
+    /// answers end up in the cache.
+    async fn send(
+        &self,
+        params: &HashMap<String, Value>,
+        forward_headers: HeaderMap,
+    ) -> (Result<(), Refusal>, Option<CacheControl>) {
+        match self.fetch(params, forward_headers).await {
+            Ok((response, cache_control)) => {
+                let res = match response.is_allowed {
+                    true => Ok(()),
+                    false => Err(Refusal::Denied(not_allowed_msg(response.error_msg))),
+                };
+                (res, cache_control)
+            }
+            Err(err) => (Err(Refusal::Failed(err)), Some(CacheControl::NoStore)),
+        }
+    }
+
+    async fn fetch(
+        &self,
+        params: &HashMap<String, Value>,
+        forward_headers: HeaderMap,
+    ) -> Result<(ConfirmationResponse, Option<CacheControl>), String> {
+        let client = http_client();
+
+        let mut headers = self.header_map()?;
+        headers.extend(forward_headers);
+        let mut req = match self.method {
+            ConfirmationMethod::Get => client.get(&self.url),
+            ConfirmationMethod::Post => client.post(&self.url),
+        }
+        .headers(headers);
+
+        if !params.is_empty() {
+            req = match self.format {
+                ConfirmationFormat::Json => req.json(params),
+                ConfirmationFormat::Form => req.form(params),
+            };
+        }
+
+        let response = req
+            .send()
+            .await
+            .map_err(|err| format!("Can't send confirmation request: {:?}", err))?;
+        let cache_control = CacheControl::from_headers(response.headers());
+        let response = response
+            .text()
+            .await
+            .map_err(|err| format!("Can't read confirmation response: {:?}", err))?;
+        let response: ConfirmationResponse = serde_json::from_str(&response)
+            .map_err(|err| format!("Can't parse confirmation response: {err} | {response}"))?;
+        Ok((response, cache_control))
+    }
+
+    fn header_map(&self) -> Result<HeaderMap, String> {
+        let mut headers = HeaderMap::new();
+        for (key, value) in self.headers.clone() {
+            let header_name =
+                HeaderName::from_bytes(key.as_bytes()).map_err(|err| err.to_string())?;
+            let header_value = HeaderValue::from_str(&value).map_err(|err| err.to_string())?;
+            headers.insert(header_name, header_value);
+        }
+        Ok(headers)
+    }
+}
+
+fn not_allowed_msg(error_msg: Option<String>) -> String {
+    format!("Not allowed: {}", error_msg.unwrap_or_default())
+}
+
+impl ConfirmationCache {
+    fn get(&self, key: &str) -> Option<Result<(), Refusal>> {
+        let entries = self.0.lock().ok()?;
+        entries
+            .get(key)
+            .filter(|(expires, _)| *expires > Instant::now())
+            .map(|(_, res)| res.clone())
+    }
+
+    fn insert(&self, key: String, res: Result<(), Refusal>, ttl: Duration, max_entries: usize) {
+        let Ok(mut entries) = self.0.lock() else {
+            return;
+        };
+        if entries.len() >= max_entries {
+            let now = Instant::now();
+            entries.retain(|_, (expires, _)| *expires > now);
+        }
+        if entries.len() < max_entries {
+            entries.insert(key, (Instant::now() + ttl, res));
+        }
+    }
+}
+
+enum CacheControl {
+    NoStore,
+    MaxAge(Duration),
+}
+
+impl CacheControl {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let value = headers.get(header::CACHE_CONTROL)?.to_str().ok()?;
+        let mut max_age = None;
+        for directive in value.split(',').map(|directive| directive.trim()) {
+            match directive.to_lowercase().as_str() {
+                "no-store" | "no-cache" => return Some(CacheControl::NoStore),
+                directive => {
+                    if let Some(secs) = directive
+                        .strip_prefix("max-age=")
+                        .and_then(|secs| secs.parse().ok())
+                    {
+                        max_age = Some(CacheControl::MaxAge(Duration::from_secs(secs)));
+                    }
+                }
+            }
+        }
+        max_age
+    }
+}
+
+/// Cache key out of the final request params, the forwarded headers and the configured headers
+/// and cookies of the incoming request
+fn cache_key(
+    cfg: &ConfirmationCacheCfg,
+    req: &HttpRequest,
+    params: &HashMap<String, Value>,
+    forward_headers: &HeaderMap,
+) -> String {
+    let params: BTreeMap<_, _> = params.iter().collect();
+    let headers: Vec<Option<&str>> = cfg
+        .vary_headers
+        .iter()
+        .map(|name| {
+            req.headers()
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+        })
+        .collect();
+    let cookies: Vec<Option<String>> = cfg
+        .vary_cookies
+        .iter()
+        .map(|name| req.cookie(name).map(|cookie| cookie.value().to_string()))
+        .collect();
+    let forwarded: Vec<(&str, &[u8])> = forward_headers
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_bytes()))
+        .collect();
+    serde_json::to_string(&(params, headers, cookies, forwarded)).unwrap_or_default()
+}
@@ -0,0 +1,238 @@
+use super::Refusal;
+use crate::config::ServerConfig;
+use actix_web::{HttpRequest, http::header, web::Data};
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+
+/// Network in CIDR notation like `10.0.0.0/8` or `fd00::/8`, a plain address stands for itself
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(try_from = "String")]
+pub struct IpNetwork(IpNet);
+
+impl TryFrom<String> for IpNetwork {
+    type Error = String;
+
+    fn try_from(network: String) -> Result<Self, Self::Error> {
+        network
+            .parse::<IpNet>()
+            .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+            .map(Self)
+            .map_err(|_| format!("Invalid ip network: {network}"))
+    }
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+}
+
+fn contains(networks: &[IpNetwork], ip: &IpAddr) -> bool {
+    networks.iter().any(|network| network.contains(ip))
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct IpRangeCfg {
+    // if set, only client addresses inside these networks are allowed
+    #[serde(default)]
+    allow: Option<Vec<IpNetwork>>,
+
+    // client addresses inside these networks are denied, even if allowed above
+    #[serde(default)]
+    deny: Vec<IpNetwork>,
+}
+
+impl IpRangeCfg {
+    pub fn is_allowed(&self, req: &HttpRequest) -> Result<(), Refusal> {
+        let ip = client_ip(req)
+            .ok_or_else(|| Refusal::Failed(String::from("Unknown client address")))?;
+        let allowed = !contains(&self.deny, &ip)
+            && self.allow.as_ref().is_none_or(|allow| contains(allow, &ip));
+        allowed
+            .then_some(())
+            .ok_or_else(|| "Access from this address not allowed".into())
+    }
+}
+
+/// Address of the caller. Behind one of the configured `trusted_proxies` it is read from the
+/// `Forwarded` or `X-Forwarded-For` header: the last hop not added by a trusted proxy.
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip().to_canonical();
+    let trusted = req
+        .app_data::<Data<ServerConfig>>()
+        .map(|cfg| cfg.trusted_proxies.as_slice())
+        .unwrap_or_default();
+    if !contains(trusted, &peer) {
+        return Some(peer);
+    }
+
+    let mut client = peer;
+    for hop in forwarded_hops(req).iter().rev() {
+        // Obfuscated or unknown hops end the chain, everything before can't be checked
+        let Some(ip) = parse_hop(hop) else {
+            break;
+        };
+        client = ip;
+        if !contains(trusted, &client) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+/// `for` values of the `Forwarded` header or, if there is none, the `X-Forwarded-For` addresses
+fn forwarded_hops(req: &HttpRequest) -> Vec<&str> {
+    let values = |name| {
+        req.headers()
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+    };
+    let forwarded: Vec<&str> = values(header::FORWARDED)
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("for").then_some(value)
+            })
+        })
+        .collect();
+    match forwarded.is_empty() {
+        true => values(header::X_FORWARDED_FOR).collect(),
+        false => forwarded,
+    }
+}
+
+/// Reads `192.0.2.1`, `192.0.2.1:4711`, `"[2001:db8::1]:4711"` or `2001:db8::1`
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    let ip = hop
+        .parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .or_else(|| hop.strip_prefix('[')?.strip_suffix(']')?.parse().ok())?;
+    Some(ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn network(network: &str) -> IpNetwork {
+        IpNetwork::try_from(network.to_string()).unwrap()
+    }
+
+    fn request(peer: &str, trusted: &[&str], headers: &[(&str, &str)]) -> HttpRequest {
+        let cfg = ServerConfig {
+            trusted_proxies: trusted.iter().map(|trusted| network(trusted)).collect(),
+            ..Default::default()
+        };
+        let mut req = TestRequest::default()
+            .peer_addr(peer.parse().unwrap())
+            .app_data(Data::new(cfg));
+        for header in headers {
+            req = req.append_header(*header);
+        }
+        req.to_http_request()
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn parses_hops() {
+        assert_eq!(parse_hop("192.0.2.1"), ip("192.0.2.1"));
+        assert_eq!(parse_hop(" 192.0.2.1:4711 "), ip("192.0.2.1"));
+        assert_eq!(parse_hop("2001:db8::1"), ip("2001:db8::1"));
+        assert_eq!(parse_hop("\"[2001:db8::1]:4711\""), ip("2001:db8::1"));
+        assert_eq!(parse_hop("\"[2001:db8::1]\""), ip("2001:db8::1"));
+        assert_eq!(parse_hop("::ffff:192.0.2.1"), ip("192.0.2.1"));
+        assert_eq!(parse_hop("unknown"), None);
+        assert_eq!(parse_hop("_hidden"), None);
+    }
+
+    #[test]
+    fn ignores_headers_of_untrusted_peers() {
+        let req = request("192.0.2.1:1234", &[], &[("X-Forwarded-For", "198.51.100.7")]);
+        assert_eq!(client_ip(&req), ip("192.0.2.1"));
+
+        let headers = [("X-Forwarded-For", "198.51.100.7")];
+        let req = request("192.0.2.1:1234", &["10.0.0.0/8"], &headers);
+        assert_eq!(client_ip(&req), ip("192.0.2.1"));
+    }
+
+    #[test]
+    fn walks_the_hops_added_by_trusted_proxies() {
+        let trusted = ["10.0.0.0/8"];
+        let headers = [("X-Forwarded-For", "203.0.113.9, 198.51.100.7, 10.0.0.2")];
+        let req = request("10.0.0.1:1234", &trusted, &headers);
+        assert_eq!(client_ip(&req), ip("198.51.100.7"));
+
+        // a spoofed first hop is never reached
+        let headers = [("X-Forwarded-For", "10.0.0.5, 198.51.100.7")];
+        let req = request("10.0.0.1:1234", &trusted, &headers);
+        assert_eq!(client_ip(&req), ip("198.51.100.7"));
+
+        // several headers are read in order
+        let headers = [("X-Forwarded-For", "198.51.100.7"), ("X-Forwarded-For", "10.0.0.2")];
+        let req = request("10.0.0.1:1234", &trusted, &headers);
+        assert_eq!(client_ip(&req), ip("198.51.100.7"));
+
+        // only trusted hops: the first one is the client
+        let headers = [("X-Forwarded-For", "10.0.0.3, 10.0.0.2")];
+        let req = request("10.0.0.1:1234", &trusted, &headers);
+        assert_eq!(client_ip(&req), ip("10.0.0.3"));
+
+        // without header the proxy is the client
+        let req = request("10.0.0.1:1234", &trusted, &[]);
+        assert_eq!(client_ip(&req), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn stops_at_unreadable_hops() {
+        let headers = [("X-Forwarded-For", "198.51.100.7, unknown, 10.0.0.2")];
+        let req = request("10.0.0.1:1234", &["10.0.0.0/8"], &headers);
+        assert_eq!(client_ip(&req), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn prefers_the_forwarded_header() {
+        let trusted = ["10.0.0.0/8", "fd00::/8"];
+        let headers = [
+            ("X-Forwarded-For", "203.0.113.9"),
+            (
+                "Forwarded",
+                "for=198.51.100.7;proto=https, For=\"[2001:db8::1]:4711\";by=10.0.0.1",
+            ),
+            ("Forwarded", "for=10.0.0.2"),
+        ];
+        let req = request("10.0.0.1:1234", &trusted, &headers);
+        assert_eq!(client_ip(&req), ip("2001:db8::1"));
+
+        let headers = [("Forwarded", "for=198.51.100.7, for=\"[fd00::2]\"")];
+        let req = request("[fd00::1]:1234", &trusted, &headers);
+        assert_eq!(client_ip(&req), ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn matches_mapped_ipv4_peers() {
+        let headers = [("X-Forwarded-For", "198.51.100.7")];
+        let req = request("[::ffff:10.0.0.1]:1234", &["10.0.0.0/8"], &headers);
+        assert_eq!(client_ip(&req), ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let range = IpRangeCfg {
+            allow: Some(vec![network("10.0.0.0/8"), network("2001:db8::/32")]),
+            deny: vec![network("10.0.0.66")],
+        };
+        let is_allowed = |peer| range.is_allowed(&request(peer, &[], &[])).is_ok();
+        assert!(is_allowed("10.1.2.3:1234"));
+        assert!(is_allowed("[2001:db8::5]:1234"));
+        assert!(!is_allowed("10.0.0.66:1234"));
+        assert!(!is_allowed("192.0.2.1:1234"));
+    }
+}
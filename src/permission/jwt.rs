@@ -0,0 +1,345 @@
+use super::{Refusal, read_token, signed_token::default_query_param};
+use crate::utils::{ParamTuple, Scope, http_client};
+use actix_web::HttpRequest;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    fmt, fs,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+
+// Unknown key ids and failed loads trigger a reload, but not more often than this
+const MIN_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Bearer json web tokens, e.g. from an OIDC provider, verified with configured keys
+#[derive(Deserialize, Clone, Debug)]
+pub struct JwtCfg {
+    keys: JwtKeySource,
+
+    // accepted signing algorithms, all of the same key family
+    #[serde(default = "default_algorithms")]
+    algorithms: Vec<Algorithm>,
+
+    // expected "iss" claim
+    issuer: Option<String>,
+
+    // accepted "aud" claims, not checked if not set
+    audience: Option<Vec<String>>,
+
+    // names of the claims holding the ids of the request
+    #[serde(default)]
+    claims: ClaimMapping,
+
+    // roles that all have to be inside the roles claim
+    #[serde(default)]
+    required_roles: Vec<String>,
+
+    // query parameter to read the token from, if there is no Authorization header
+    #[serde(default = "default_query_param")]
+    query_param: String,
+
+    // keys are reloaded after this time
+    #[serde(default = "default_refresh_sec")]
+    refresh_sec: u64,
+
+    #[serde(skip)]
+    cache: KeyCache,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum JwtKeySource {
+    // Path to a public key pem file
+    Pem(String),
+
+    // Path to a JWKS json file
+    JwksFile(String),
+
+    // Url of a JWKS endpoint
+    JwksUrl(String),
+}
+
+/// Claim names, nested claims can be addressed with dots (`realm_access.roles`)
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ClaimMapping {
+    // claim with the allowed lobby id(s), required
+    lobby_id: String,
+
+    // claim with the allowed room id(s), all rooms allowed if missing in the token
+    room_id: Option<String>,
+
+    // claim with the roles of the user
+    roles: String,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            lobby_id: String::from("lobby_id"),
+            room_id: None,
+            roles: String::from("roles"),
+        }
+    }
+}
+
+fn default_algorithms() -> Vec<Algorithm> {
+    vec![Algorithm::RS256]
+}
+
+fn default_refresh_sec() -> u64 {
+    60 * 60
+}
+
+#[derive(Clone, Default)]
+struct KeyCache(Arc<RwLock<Option<LoadedKeys>>>);
+
+impl fmt::Debug for KeyCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("KeyCache")
+    }
+}
+
+/// Keys of the last successful load, kept while reloading fails
+struct LoadedKeys {
+    // `None` until a load succeeded
+    loaded_at: Option<Instant>,
+    attempted_at: Instant,
+
+    // error of the last attempt
+    error: Option<String>,
+    keys: Vec<(Option<String>, DecodingKey)>,
+}
+
+impl LoadedKeys {
+    /// Missing keys are errors, not denials: the key source may just be unreachable
+    fn key(&self, kid: Option<&str>) -> Result<DecodingKey, Refusal> {
+        self.find(kid).ok_or_else(|| {
+            Refusal::Failed(self.error.clone().unwrap_or(String::from("Unknown key id")))
+        })
+    }
+
+    /// Reloaded after `refresh_after` or for an unknown key id, not more often than
+    /// `MIN_RELOAD_INTERVAL`
+    fn needs_reload(&self, kid: Option<&str>, refresh_after: Duration) -> bool {
+        let is_outdated = self
+            .loaded_at
+            .is_none_or(|loaded_at| loaded_at.elapsed() >= refresh_after);
+        self.attempted_at.elapsed() >= MIN_RELOAD_INTERVAL.min(refresh_after)
+            && (is_outdated || self.find(kid).is_none())
+    }
+
+    fn find(&self, kid: Option<&str>) -> Option<DecodingKey> {
+        self.keys
+            .iter()
+            .find(|(key_id, _)| kid.is_none() || key_id.is_none() || key_id.as_deref() == kid)
+            .map(|(_, key)| key.clone())
+    }
+}
+
+impl JwtCfg {
+    pub async fn is_allowed<T: ParamTuple>(
+        &self,
+        req: &HttpRequest,
+        params: &T,
+    ) -> Result<(), Refusal> {
+        let token = read_token(req, &self.query_param).ok_or("No token")?;
+        let header = decode_header(&token)
+            .map_err(|err| Refusal::Failed(format!("Malformed token: {err}")))?;
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = self.algorithms.clone();
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(audience),
+            None => validation.validate_aud = false,
+        }
+
+        let claims = decode::<Value>(&token, &key, &validation)
+            .map_err(|err| format!("Invalid token: {err}"))?
+            .claims;
+        Ok(self.check_claims(&claims, params.scope())?)
+    }
+
+    fn check_claims(&self, claims: &Value, scope: Scope) -> Result<(), String> {
+        let lobby_ids = claim_values(claims, &self.claims.lobby_id)
+            .ok_or_else(|| format!("Token has no {} claim", self.claims.lobby_id))?;
+        if !lobby_ids.contains(&scope.lobby_id.to_string()) {
+            return Err(String::from("Token not valid for this lobby"));
+        }
+
+        let room_ids = self
+            .claims
+            .room_id
+            .as_ref()
+            .and_then(|claim| claim_values(claims, claim));
+        match (room_ids, scope.room_id) {
+            (Some(room_ids), Some(room_id)) if !room_ids.contains(&room_id.to_string()) => {
+                return Err(String::from("Token not valid for this room"));
+            }
+            (Some(_), None) => return Err(String::from("Token is limited to single rooms")),
+            _ => {}
+        }
+
+        let roles = claim_values(claims, &self.claims.roles).unwrap_or_default();
+        match self
+            .required_roles
+            .iter()
+            .find(|role| !roles.contains(role))
+        {
+            Some(role) => Err(format!("Role {role} missing")),
+            None => Ok(()),
+        }
+    }
+
+    /// A failed reload keeps the old keys, so tokens can be checked while the key source is down
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, Refusal> {
+        let refresh_after = Duration::from_secs(self.refresh_sec);
+        if let Some(loaded) = self.cache.0.read().await.as_ref()
+            && !loaded.needs_reload(kid, refresh_after)
+        {
+            return loaded.key(kid);
+        }
+
+        // one reload at a time, requests waiting for it use its keys
+        let mut cache = self.cache.0.write().await;
+        if let Some(loaded) = cache.as_ref()
+            && !loaded.needs_reload(kid, refresh_after)
+        {
+            return loaded.key(kid);
+        }
+        let res = self.load_keys().await;
+        let now = Instant::now();
+        let loaded = match (res, cache.take()) {
+            (Ok(keys), _) => LoadedKeys {
+                loaded_at: Some(now),
+                attempted_at: now,
+                error: None,
+                keys,
+            },
+            (Err(err), Some(stale)) => {
+                warn!("Can't reload jwt keys, the old ones are used: {err}");
+                LoadedKeys {
+                    attempted_at: now,
+                    error: Some(err),
+                    ..stale
+                }
+            }
+            (Err(err), None) => LoadedKeys {
+                loaded_at: None,
+                attempted_at: now,
+                error: Some(err),
+                keys: Vec::new(),
+            },
+        };
+        cache.insert(loaded).key(kid)
+    }
+
+    async fn load_keys(&self) -> Result<Vec<(Option<String>, DecodingKey)>, String> {
+        let jwks = match &self.keys {
+            JwtKeySource::Pem(path) => {
+                let pem = fs::read(path).map_err(|err| format!("Can't read {path}: {err}"))?;
+                return Ok(vec![(None, self.pem_key(&pem)?)]);
+            }
+            JwtKeySource::JwksFile(path) => {
+                fs::read_to_string(path).map_err(|err| format!("Can't read {path}: {err}"))?
+            }
+            JwtKeySource::JwksUrl(url) => http_client()
+                .get(url)
+                .send()
+                .await
+                .map_err(|err| format!("Can't fetch jwks: {err}"))?
+                .text()
+                .await
+                .map_err(|err| format!("Can't read jwks: {err}"))?,
+        };
+        let jwks: JwkSet =
+            serde_json::from_str(&jwks).map_err(|err| format!("Invalid jwks: {err}"))?;
+
+        let keys = jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| match DecodingKey::from_jwk(jwk) {
+                Ok(key) => Some((jwk.common.key_id.clone(), key)),
+                Err(err) => {
+                    warn!("Skip unusable jwk {:?}: {err}", jwk.common.key_id);
+                    None
+                }
+            })
+            .collect();
+        Ok(keys)
+    }
+
+    fn pem_key(&self, pem: &[u8]) -> Result<DecodingKey, String> {
+        use Algorithm::*;
+        match self.algorithms.first().copied().unwrap_or_default() {
+            RS256 | RS384 | RS512 | PS256 | PS384 | PS512 => DecodingKey::from_rsa_pem(pem),
+            ES256 | ES384 => DecodingKey::from_ec_pem(pem),
+            EdDSA => DecodingKey::from_ed_pem(pem),
+            HS256 | HS384 | HS512 => return Ok(DecodingKey::from_secret(pem)),
+        }
+        .map_err(|err| format!("Invalid pem key: {err}"))
+    }
+}
+
+/// Collects a string, number or list claim as strings, `path` can point into nested objects
+fn claim_values(claims: &Value, path: &str) -> Option<Vec<String>> {
+    let value = path
+        .split('.')
+        .try_fold(claims, |value, key| value.get(key))?;
+    let to_string = |value: &Value| match value {
+        Value::String(str) => Some(str.clone()),
+        Value::Number(num) => Some(num.to_string()),
+        _ => None,
+    };
+    match value {
+        Value::Array(values) => Some(values.iter().filter_map(to_string).collect()),
+        value => to_string(value).map(|value| vec![value]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use uuid::Uuid;
+
+    const JWKS: &str = r#"{ "keys": [{ "kty": "oct", "kid": "k1", "k": "c2VjcmV0" }] }"#;
+
+    fn cfg(path: &str) -> JwtCfg {
+        let cfg = format!(r#"{{ "keys": {{ "JwksFile": "{path}" }}, "refresh_sec": 0 }}"#);
+        serde_json::from_str(&cfg).unwrap()
+    }
+
+    #[actix::test]
+    async fn keeps_the_old_keys_if_reloading_fails() {
+        let path = env::temp_dir().join(format!("wim-jwks-{}.json", Uuid::new_v4()));
+        fs::write(&path, JWKS).unwrap();
+        let cfg = cfg(&path.to_string_lossy());
+        assert!(cfg.decoding_key(Some("k1")).await.is_ok());
+
+        // reloaded on every use with refresh_sec 0
+        fs::remove_file(&path).unwrap();
+        assert!(cfg.decoding_key(Some("k1")).await.is_ok());
+        let res = cfg.decoding_key(Some("k2")).await;
+        assert!(matches!(res, Err(Refusal::Failed(err)) if err.starts_with("Can't read")));
+    }
+
+    #[actix::test]
+    async fn fails_without_keys() {
+        let path = env::temp_dir().join(format!("wim-jwks-{}.json", Uuid::new_v4()));
+        let cfg = cfg(&path.to_string_lossy());
+        assert!(matches!(cfg.decoding_key(None).await, Err(Refusal::Failed(_))));
+
+        // found once the key source is back
+        fs::write(&path, JWKS).unwrap();
+        assert!(cfg.decoding_key(None).await.is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,527 @@
+use crate::{
+    LobbyId,
+    utils::{ParamTuple, matches_wildcard},
+};
+use Restriction::*;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, http::header, web::Query};
+use audit::{AuditLogCfg, Outcome};
+use confirmation::ConfirmationRequest;
+use futures_util::future::LocalBoxFuture;
+use log::warn;
+use ip::IpRangeCfg;
+use jwt::JwtCfg;
+use origin::{OriginPattern, is_allowed_origin};
+use owner::is_owner;
+use rate_limit::{RateLimit, RateLimitsTaken, rate_limited_msg, too_many_requests};
+use serde::{Deserialize, Serialize};
+use signed_token::SignedTokenCfg;
+use std::{collections::HashMap, fmt, time::Duration};
+use ts_rs::TS;
+
+pub mod audit;
+pub mod confirmation;
+pub mod ip;
+pub mod jwt;
+pub mod origin;
+pub mod owner;
+pub mod rate_limit;
+pub mod signed_token;
+
+/// Every api call guarded by a permission, named like the matching `Permissions` field
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum Action {
+    GetRoomList,
+    GetRoomImgList,
+    GetImgThumb,
+    GetImgBig,
+    GetImgOriginal,
+    UploadImg,
+    DeleteLobby,
+    DeleteRoom,
+    DeleteImg,
+    SendChatMessage,
+    GetPresence,
+    ConnectNotifications,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::GetRoomList => "get_room_list",
+            Action::GetRoomImgList => "get_room_img_list",
+            Action::GetImgThumb => "get_img_thumb",
+            Action::GetImgBig => "get_img_big",
+            Action::GetImgOriginal => "get_img_original",
+            Action::UploadImg => "upload_img",
+            Action::DeleteLobby => "delete_lobby",
+            Action::DeleteRoom => "delete_room",
+            Action::DeleteImg => "delete_img",
+            Action::SendChatMessage => "send_chat_message",
+            Action::GetPresence => "get_presence",
+            Action::ConnectNotifications => "connect_notifications",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct Permissions {
+    pub get_room_list: Permission,
+    pub get_room_img_list: Permission,
+    pub get_img_thumb: Permission,
+    pub get_img_big: Permission,
+
+    // permission of `get_img_big` if not set
+    #[serde(default)]
+    pub get_img_original: Option<Permission>,
+    pub upload_img: Permission,
+    pub delete_lobby: Permission,
+    pub delete_room: Permission,
+    pub delete_img: Permission,
+    pub send_chat_message: Permission,
+
+    // permission of `connect_notifications` if not set
+    #[serde(default)]
+    pub get_presence: Option<Permission>,
+    #[serde(default)]
+    pub connect_notifications: Permission,
+
+    // pages allowed to use the api at all, also used as CORS origins
+    #[serde(default)]
+    pub allowed_origins: Option<Vec<OriginPattern>>,
+
+    // replaces permissions for matching lobbies, the first override listing the action wins
+    #[serde(default)]
+    pub lobby_overrides: Vec<LobbyPermissions>,
+
+    // request limits per action, checked before the permission
+    #[serde(default)]
+    pub rate_limits: HashMap<Action, Vec<RateLimit>>,
+
+    // every permission decision is written to this file
+    #[serde(default)]
+    pub audit_log: Option<AuditLogCfg>,
+}
+
+impl Permissions {
+    pub async fn is_allowed<T: ParamTuple>(
+        &self,
+        action: Action,
+        req: &HttpRequest,
+        params: &T,
+    ) -> Result<(), Refusal> {
+        if let Some(origins) = &self.allowed_origins {
+            is_allowed_origin(origins, req)?;
+        }
+        self.get(action, &params.scope().lobby_id)
+            .is_allowed(action, req, params)
+            .await
+    }
+
+    /// Counts the request against every rate limit of the action, even if one is already exceeded
+    pub fn take_rate_limits<T: ParamTuple>(
+        &self,
+        action: Action,
+        req: &HttpRequest,
+        params: &T,
+    ) -> Result<(), Duration> {
+        let Some(limits) = self.rate_limits.get(&action) else {
+            return Ok(());
+        };
+        limits
+            .iter()
+            .filter_map(|limit| limit.take(req, params).err())
+            .max()
+            .map_or(Ok(()), Err)
+    }
+
+    pub fn get(&self, action: Action, lobby_id: &LobbyId) -> &Permission {
+        let overridden = self
+            .lobby_overrides
+            .iter()
+            .filter(|overrides| overrides.matches(&lobby_id.to_string()))
+            .find_map(|overrides| overrides.permissions.get(&action));
+        match (overridden, action) {
+            (Some(permission), _) => permission,
+            // originals are never easier to get than big images
+            (None, Action::GetImgOriginal) if self.get_img_original.is_none() => {
+                self.get(Action::GetImgBig, lobby_id)
+            }
+            // members are visible to everyone who can receive the events of the lobby
+            (None, Action::GetPresence) if self.get_presence.is_none() => {
+                self.get(Action::ConnectNotifications, lobby_id)
+            }
+            (None, action) => self.get_default(action),
+        }
+    }
+
+    fn get_default(&self, action: Action) -> &Permission {
+        match action {
+            Action::GetRoomList => &self.get_room_list,
+            Action::GetRoomImgList => &self.get_room_img_list,
+            Action::GetImgThumb => &self.get_img_thumb,
+            Action::GetImgBig => &self.get_img_big,
+            Action::GetImgOriginal => self.get_img_original.as_ref().unwrap_or(&self.get_img_big),
+            Action::UploadImg => &self.upload_img,
+            Action::DeleteLobby => &self.delete_lobby,
+            Action::DeleteRoom => &self.delete_room,
+            Action::DeleteImg => &self.delete_img,
+            Action::SendChatMessage => &self.send_chat_message,
+            Action::GetPresence => self
+                .get_presence
+                .as_ref()
+                .unwrap_or(&self.connect_notifications),
+            Action::ConnectNotifications => &self.connect_notifications,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LobbyPermissions {
+    // lobby ids, `*` matches any characters
+    lobbies: Vec<String>,
+
+    // actions missing here keep the permission of the next matching override or the default
+    permissions: HashMap<Action, Permission>,
+}
+
+impl LobbyPermissions {
+    fn matches(&self, lobby_id: &str) -> bool {
+        self.lobbies
+            .iter()
+            .any(|pattern| matches_wildcard(pattern, lobby_id))
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Permission {
+    restriction: Restriction,
+    url_whitelist: Option<Vec<OriginPattern>>,
+}
+
+impl Permission {
+    pub async fn is_allowed<T: ParamTuple>(
+        &self,
+        action: Action,
+        req: &HttpRequest,
+        params: &T,
+    ) -> Result<(), Refusal> {
+        if let Some(urls) = &self.url_whitelist {
+            is_allowed_origin(urls, req)?;
+        }
+        self.restriction.is_allowed(action, req, params).await
+    }
+}
+
+impl Default for Permission {
+    fn default() -> Self {
+        Self {
+            restriction: Restriction::AllowedToAll,
+            url_whitelist: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum Restriction {
+    AllowedToAll,
+    NeedsConfirmation(ConfirmationRequest),
+    SignedToken(SignedTokenCfg),
+    Jwt(JwtCfg),
+    UrlWhitelist(Vec<OriginPattern>),
+    IpRange(IpRangeCfg),
+    OwnerOrElse(Box<Restriction>),
+    AllOf(Vec<Restriction>),
+    AnyOf(Vec<Restriction>),
+    Not(Box<Restriction>),
+    Denied,
+}
+
+impl Restriction {
+    /// Boxed, because composite restrictions evaluate their inner restrictions recursively
+    pub fn is_allowed<'a, T: ParamTuple>(
+        &'a self,
+        action: Action,
+        req: &'a HttpRequest,
+        params: &'a T,
+    ) -> LocalBoxFuture<'a, Result<(), Refusal>> {
+        Box::pin(async move {
+            match self {
+                AllowedToAll => Ok(()),
+                NeedsConfirmation(confirm_req) => confirm_req.is_allowed(action, req, params).await,
+                SignedToken(token_cfg) => token_cfg.is_allowed(action, req, params),
+                Jwt(jwt_cfg) => jwt_cfg.is_allowed(req, params).await,
+                UrlWhitelist(urls) => Ok(is_allowed_origin(urls, req)?),
+                IpRange(ip_cfg) => ip_cfg.is_allowed(req),
+                OwnerOrElse(fallback) => match is_owner(req, params) {
+                    true => Ok(()),
+                    false => fallback.is_allowed(action, req, params).await,
+                },
+                AllOf(restrictions) => {
+                    for restriction in restrictions {
+                        restriction.is_allowed(action, req, params).await?;
+                    }
+                    Ok(())
+                }
+                AnyOf(restrictions) => {
+                    let mut refusals = Vec::new();
+                    for restriction in restrictions {
+                        match restriction.is_allowed(action, req, params).await {
+                            Ok(()) => return Ok(()),
+                            Err(refusal) => refusals.push(refusal),
+                        }
+                    }
+                    // one restriction that couldn't be evaluated might have allowed it
+                    let failed = refusals.iter().any(Refusal::is_failed);
+                    let msg = refusals
+                        .iter()
+                        .map(Refusal::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" | ");
+                    Err(match failed {
+                        true => Refusal::Failed(msg),
+                        false => Refusal::Denied(msg),
+                    })
+                }
+                // fails closed, an error of the inner restriction is no decision to invert
+                Not(restriction) => match restriction.is_allowed(action, req, params).await {
+                    Ok(()) => Err(Refusal::Denied(self.to_string())),
+                    Err(Refusal::Denied(_)) => Ok(()),
+                    Err(failed) => Err(failed),
+                },
+                Denied => Err(Refusal::Denied(self.to_string())),
+            }
+        })
+    }
+
+    /// Name of the restriction including the inner restrictions, e.g. `AllOf(IpRange, Jwt)`
+    pub fn describe(&self) -> String {
+        let list = |restrictions: &[Restriction]| {
+            restrictions
+                .iter()
+                .map(|restriction| restriction.describe())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            AllowedToAll => String::from("AllowedToAll"),
+            NeedsConfirmation(_) => String::from("NeedsConfirmation"),
+            SignedToken(_) => String::from("SignedToken"),
+            Jwt(_) => String::from("Jwt"),
+            UrlWhitelist(_) => String::from("UrlWhitelist"),
+            IpRange(_) => String::from("IpRange"),
+            OwnerOrElse(fallback) => format!("OwnerOrElse({})", fallback.describe()),
+            AllOf(restrictions) => format!("AllOf({})", list(restrictions)),
+            AnyOf(restrictions) => format!("AnyOf({})", list(restrictions)),
+            Not(restriction) => format!("Not({})", restriction.describe()),
+            Denied => String::from("Denied"),
+        }
+    }
+}
+
+impl fmt::Display for Restriction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AllowedToAll => "Allowed",
+            NeedsConfirmation(_) => "Needs confirmation from other server",
+            SignedToken(_) => "Needs signed token",
+            Jwt(_) => "Needs json web token",
+            UrlWhitelist(_) => "Access from this url not allowed",
+            IpRange(_) => "Access from this address not allowed",
+            OwnerOrElse(_) => "Needs to be the uploader",
+            AllOf(_) => "Needs all of the restrictions",
+            AnyOf(_) => "Needs one of the restrictions",
+            Not(_) => "Access denied",
+            Denied => "Access denied",
+        })
+    }
+}
+
+/// Reads a token from the `Authorization: Bearer` header or the given query parameter
+pub fn read_token(req: &HttpRequest, query_param: &str) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    bearer.or_else(|| {
+        Query::<HashMap<String, String>>::from_query(req.query_string())
+            .ok()
+            .and_then(|mut query| query.remove(query_param))
+    })
+}
+
+/// Why a restriction doesn't allow a call
+#[derive(Clone, Debug)]
+pub enum Refusal {
+    /// The restriction was evaluated and doesn't allow the call
+    Denied(String),
+
+    /// The restriction couldn't be evaluated, e.g. the confirmation server is unreachable
+    Failed(String),
+}
+
+impl Refusal {
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Refusal::Failed(_))
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Refusal::Denied(msg) | Refusal::Failed(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl From<String> for Refusal {
+    fn from(msg: String) -> Self {
+        Refusal::Denied(msg)
+    }
+}
+
+impl From<&str> for Refusal {
+    fn from(msg: &str) -> Self {
+        Refusal::Denied(msg.to_string())
+    }
+}
+
+impl From<Refusal> for String {
+    fn from(refusal: Refusal) -> Self {
+        refusal.to_string()
+    }
+}
+
+pub enum Denied {
+    RateLimited(Duration),
+    Forbidden(String),
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Denied::RateLimited(retry_after) => f.write_str(&rate_limited_msg(*retry_after)),
+            Denied::Forbidden(msg) => f.write_str(msg),
+        }
+    }
+}
+
+fn audit<T: ParamTuple>(
+    permissions: &Permissions,
+    action: Action,
+    req: &HttpRequest,
+    params: &T,
+    outcome: Outcome,
+) {
+    if let Some(audit_log) = &permissions.audit_log {
+        let restriction = &permissions
+            .get(action, &params.scope().lobby_id)
+            .restriction;
+        audit_log.log(action, req, params, restriction.describe(), outcome);
+    }
+}
+
+/// Rate limits of one call, a rate limited call is written to the audit log
+pub fn limit_rate<T: ParamTuple>(
+    permissions: &Permissions,
+    action: Action,
+    req: &HttpRequest,
+    params: &T,
+) -> Result<(), Duration> {
+    let res = permissions.take_rate_limits(action, req, params);
+    if let Err(retry_after) = res {
+        audit(permissions, action, req, params, Outcome::rate_limited(retry_after));
+    }
+    res
+}
+
+/// Rate limits and permission of one call, written to the audit log. The rate limits are
+/// skipped if the `RateLimited` middleware of the route already took them.
+pub async fn authorize<T: ParamTuple>(
+    permissions: &Permissions,
+    action: Action,
+    req: &HttpRequest,
+    params: &T,
+) -> Result<(), Denied> {
+    let limits_taken = req
+        .extensions()
+        .get::<RateLimitsTaken>()
+        .is_some_and(|taken| taken.0 == action);
+    if !limits_taken {
+        limit_rate(permissions, action, req, params).map_err(Denied::RateLimited)?;
+    }
+    let res = permissions.is_allowed(action, req, params).await;
+    audit(permissions, action, req, params, Outcome::from_result(&res));
+    res.map_err(|refusal| {
+        if let Refusal::Failed(err) = &refusal {
+            warn!("Can't check permission {}: {err}", action.as_str());
+        }
+        Denied::Forbidden(refusal.into())
+    })
+}
+
+pub async fn check<T: ParamTuple>(
+    permissions: &Permissions,
+    action: Action,
+    req: &HttpRequest,
+    params: &T,
+) -> Option<HttpResponse> {
+    match authorize(permissions, action, req, params).await {
+        Ok(()) => None,
+        Err(Denied::RateLimited(retry_after)) => Some(too_many_requests(req, retry_after)),
+        Err(Denied::Forbidden(msg)) => Some(HttpResponse::Forbidden().body(msg)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn denied() -> Permission {
+        Permission {
+            restriction: Restriction::Denied,
+            url_whitelist: None,
+        }
+    }
+
+    fn is_denied(permissions: &Permissions, action: Action) -> bool {
+        let permission = permissions.get(action, &Uuid::nil());
+        matches!(permission.restriction, Restriction::Denied)
+    }
+
+    #[test]
+    fn unset_permissions_fall_back_to_the_related_one() {
+        let permissions = Permissions {
+            get_img_big: denied(),
+            connect_notifications: denied(),
+            ..Default::default()
+        };
+        assert!(is_denied(&permissions, Action::GetImgOriginal));
+        assert!(is_denied(&permissions, Action::GetPresence));
+
+        let permissions = Permissions {
+            get_img_original: Some(Permission::default()),
+            get_presence: Some(Permission::default()),
+            ..permissions
+        };
+        assert!(!is_denied(&permissions, Action::GetImgOriginal));
+        assert!(!is_denied(&permissions, Action::GetPresence));
+    }
+
+    #[test]
+    fn fallbacks_follow_lobby_overrides() {
+        let permissions = Permissions {
+            lobby_overrides: vec![LobbyPermissions {
+                lobbies: vec![String::from("*")],
+                permissions: HashMap::from([(Action::ConnectNotifications, denied())]),
+            }],
+            ..Default::default()
+        };
+        assert!(is_denied(&permissions, Action::GetPresence));
+        assert!(!is_denied(&permissions, Action::GetImgOriginal));
+    }
+}
@@ -0,0 +1,223 @@
+use crate::utils::matches_wildcard;
+use actix_web::{HttpRequest, http::header};
+use reqwest::Url;
+use serde::Deserialize;
+
+/// Allowed page, e.g. `https://app.example.com`, `https://*.example.com:8080` or
+/// `app.example.com/embed`. Without scheme http and https are allowed, a path matches itself and
+/// everything below it.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct OriginPattern {
+    scheme: Option<String>,
+    host: String,
+    port: Option<u16>,
+    path: Option<String>,
+}
+
+impl TryFrom<String> for OriginPattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        let (scheme, rest) = match pattern.split_once("://") {
+            Some((scheme, rest)) => (Some(scheme.to_lowercase()), rest),
+            None => (None, pattern.as_str()),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], Some(rest[idx..].trim_end_matches('/'))),
+            None => (rest, None),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("Invalid port in origin pattern {pattern}"))?;
+                (host, Some(port))
+            }
+            None => (authority, None),
+        };
+        if host.is_empty() {
+            return Err(format!("Origin pattern without host: {pattern}"));
+        }
+        Ok(Self {
+            scheme,
+            host: host.to_lowercase(),
+            port,
+            path: path.filter(|path| !path.is_empty()).map(String::from),
+        })
+    }
+}
+
+impl OriginPattern {
+    fn matches_origin(&self, url: &Url) -> bool {
+        let scheme_matches = match &self.scheme {
+            Some(scheme) => scheme == url.scheme(),
+            None => matches!(url.scheme(), "http" | "https"),
+        };
+        let port_matches = match self.port {
+            Some(port) => url.port_or_known_default() == Some(port),
+            None => url.port().is_none(),
+        };
+        scheme_matches
+            && port_matches
+            && url
+                .host_str()
+                .is_some_and(|host| matches_wildcard(&self.host, host))
+    }
+
+    fn matches_path(&self, url: &Url) -> bool {
+        let Some(prefix) = &self.path else {
+            return true;
+        };
+        url.path()
+            .strip_prefix(prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    /// The `Origin` header decides about the origin if the browser sent one. Patterns with a
+    /// path additionally need a matching `Referer`, because `Origin` never contains a path.
+    fn matches(&self, origin: Option<&Url>, referer: Option<&Url>) -> bool {
+        if let Some(origin) = origin
+            && !self.matches_origin(origin)
+        {
+            return false;
+        }
+        match (origin, referer, &self.path) {
+            (Some(_), _, None) => true,
+            (_, Some(referer), _) => self.matches_origin(referer) && self.matches_path(referer),
+            _ => false,
+        }
+    }
+
+    pub fn matches_header(&self, origin: &str) -> bool {
+        Url::parse(origin).is_ok_and(|origin| self.matches_origin(&origin))
+    }
+}
+
+pub fn is_allowed_origin(patterns: &[OriginPattern], req: &HttpRequest) -> Result<(), String> {
+    let read_url = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| Url::parse(value).ok())
+    };
+    // Sandboxed pages and privacy redirects send "null", which can't be parsed and counts as missing
+    let origin = read_url(header::ORIGIN);
+    let referer = read_url(header::REFERER);
+    if origin.is_none() && referer.is_none() {
+        return Err(String::from("No origin or referer"));
+    }
+
+    patterns
+        .iter()
+        .any(|pattern| pattern.matches(origin.as_ref(), referer.as_ref()))
+        .then_some(())
+        .ok_or_else(|| String::from("Access from this url not allowed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn patterns(patterns: &[&str]) -> Vec<OriginPattern> {
+        patterns
+            .iter()
+            .map(|pattern| OriginPattern::try_from(pattern.to_string()).unwrap())
+            .collect()
+    }
+
+    fn is_allowed(patterns: &[OriginPattern], origin: Option<&str>, referer: Option<&str>) -> bool {
+        let mut req = TestRequest::default();
+        if let Some(origin) = origin {
+            req = req.insert_header((header::ORIGIN, origin));
+        }
+        if let Some(referer) = referer {
+            req = req.insert_header((header::REFERER, referer));
+        }
+        is_allowed_origin(patterns, &req.to_http_request()).is_ok()
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(OriginPattern::try_from(String::from("https://")).is_err());
+        assert!(OriginPattern::try_from(String::from("example.com:http")).is_err());
+        assert!(OriginPattern::try_from(String::from(":8080")).is_err());
+    }
+
+    #[test]
+    fn matches_scheme_and_host() {
+        let allowed = patterns(&["https://app.example.com"]);
+        assert!(is_allowed(&allowed, Some("https://app.example.com"), None));
+        assert!(is_allowed(&allowed, Some("https://APP.example.com"), None));
+        assert!(!is_allowed(&allowed, Some("http://app.example.com"), None));
+        assert!(!is_allowed(&allowed, Some("https://example.com"), None));
+        assert!(!is_allowed(&allowed, Some("https://app.example.com.evil.org"), None));
+
+        // without scheme http and https are allowed
+        let allowed = patterns(&["app.example.com"]);
+        assert!(is_allowed(&allowed, Some("http://app.example.com"), None));
+        assert!(is_allowed(&allowed, Some("https://app.example.com"), None));
+        assert!(!is_allowed(&allowed, Some("ftp://app.example.com"), None));
+    }
+
+    #[test]
+    fn matches_wildcard_hosts() {
+        let allowed = patterns(&["https://*.example.com"]);
+        assert!(is_allowed(&allowed, Some("https://app.example.com"), None));
+        assert!(is_allowed(&allowed, Some("https://a.b.example.com"), None));
+        assert!(!is_allowed(&allowed, Some("https://example.com"), None));
+        assert!(!is_allowed(&allowed, Some("https://evilexample.com"), None));
+    }
+
+    #[test]
+    fn matches_ports() {
+        let allowed = patterns(&["https://*.example.com:8080"]);
+        assert!(is_allowed(&allowed, Some("https://app.example.com:8080"), None));
+        assert!(!is_allowed(&allowed, Some("https://app.example.com"), None));
+        assert!(!is_allowed(&allowed, Some("https://app.example.com:8081"), None));
+
+        // the default port of the scheme can be written out
+        let allowed = patterns(&["https://app.example.com:443"]);
+        assert!(is_allowed(&allowed, Some("https://app.example.com"), None));
+
+        // without port only the default one is allowed
+        let allowed = patterns(&["https://app.example.com"]);
+        assert!(!is_allowed(&allowed, Some("https://app.example.com:8080"), None));
+    }
+
+    #[test]
+    fn paths_need_a_matching_referer() {
+        let allowed = patterns(&["https://app.example.com/embed/"]);
+        let origin = Some("https://app.example.com");
+        assert!(is_allowed(&allowed, origin, Some("https://app.example.com/embed")));
+        assert!(is_allowed(&allowed, origin, Some("https://app.example.com/embed/a?b=c")));
+        assert!(!is_allowed(&allowed, origin, Some("https://app.example.com/embedded")));
+        assert!(!is_allowed(&allowed, origin, Some("https://app.example.com/")));
+        assert!(!is_allowed(&allowed, origin, None));
+        assert!(is_allowed(&allowed, None, Some("https://app.example.com/embed/a")));
+
+        // the origin still has to match if the referer does
+        let referer = Some("https://app.example.com/embed");
+        assert!(!is_allowed(&allowed, Some("https://evil.org"), referer));
+    }
+
+    #[test]
+    fn falls_back_to_the_referer() {
+        let allowed = patterns(&["https://app.example.com"]);
+        assert!(is_allowed(&allowed, None, Some("https://app.example.com/page")));
+        assert!(is_allowed(&allowed, Some("null"), Some("https://app.example.com/page")));
+        assert!(!is_allowed(&allowed, None, Some("https://evil.org/page")));
+        assert!(!is_allowed(&allowed, Some("null"), None));
+        assert!(!is_allowed(&allowed, None, None));
+    }
+
+    #[test]
+    fn any_pattern_can_match() {
+        let allowed = patterns(&["https://app.example.com", "http://localhost:3000"]);
+        assert!(is_allowed(&allowed, Some("http://localhost:3000"), None));
+        assert!(is_allowed(&allowed, Some("https://app.example.com"), None));
+        assert!(!is_allowed(&allowed, Some("http://localhost:3001"), None));
+        assert!(allowed[1].matches_header("http://localhost:3000"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{
+    ImgId, LobbyId, RoomId, SessionId,
+    config::ServerConfig,
+    utils::{ParamTuple, get_session_id},
+};
+use actix_web::{HttpRequest, web::Data};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const OWNER_TOKEN_HEADER: &str = "X-Owner-Token";
+
+/// Uploader of an image, stored as `<room>/owner/<img_id>.json`
+#[derive(Serialize, Deserialize)]
+struct Owner {
+    session_id: Option<SessionId>,
+
+    // sha-256 of the owner token returned by the upload
+    token_hash: String,
+}
+
+pub fn owner_path(img_storage_path: &str, params: (LobbyId, RoomId, ImgId)) -> PathBuf {
+    Path::new(img_storage_path)
+        .join(params.0.to_string())
+        .join(params.1.to_string())
+        .join("owner")
+        .join(format!("{}.json", params.2))
+}
+
+/// Remembers the uploader of a new image and returns the owner token for it
+pub fn save_owner(
+    img_storage_path: &str,
+    params: (LobbyId, RoomId, ImgId),
+    session_id: Option<SessionId>,
+) -> Result<String, String> {
+    let token = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+    let owner = Owner {
+        session_id,
+        token_hash: hash_token(&token),
+    };
+    let path = owner_path(img_storage_path, params);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| format!("Could not create owner folder: {err}"))?;
+    }
+    let owner = serde_json::to_string(&owner).map_err(|err| err.to_string())?;
+    fs::write(path, owner).map_err(|err| format!("Could not save owner: {err}"))?;
+    Ok(token)
+}
+
+fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// True if the caller uploaded the image with the same websocket session or sends its owner
+/// token as `X-Owner-Token` header
+pub fn is_owner<T: ParamTuple>(req: &HttpRequest, params: &T) -> bool {
+    let scope = params.scope();
+    let (Some(room_id), Some(img_id), Some(cfg)) = (
+        scope.room_id,
+        scope.img_id,
+        req.app_data::<Data<ServerConfig>>(),
+    ) else {
+        return false;
+    };
+    let path = owner_path(&cfg.images_storage_path, (scope.lobby_id, room_id, img_id));
+    let Some(owner) = fs::read_to_string(path)
+        .ok()
+        .and_then(|owner| serde_json::from_str::<Owner>(&owner).ok())
+    else {
+        return false;
+    };
+
+    let same_session = owner
+        .session_id
+        .is_some_and(|session_id| get_session_id(req) == Some(session_id));
+    same_session
+        || read_owner_token(req).is_some_and(|token| hash_token(token) == owner.token_hash)
+}
+
+// not read from the query string, where it would end up in access logs and browser history
+fn read_owner_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(OWNER_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+}
@@ -0,0 +1,362 @@
+use super::{Action, ip::client_ip, limit_rate};
+use crate::{
+    ImgId, LobbyId, RoomId,
+    chunked_upload::{UploadId, read_session},
+    config::ServerConfig,
+    notification::{
+        internal_messages::{SystemNotification, SystemNotificationType},
+        server::NotifyServer,
+    },
+    utils::{ParamTuple, get_session_id},
+};
+use actix::Addr;
+use actix_web::{
+    Error, HttpMessage, HttpRequest, HttpResponse,
+    body::EitherBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    http::header,
+    web::Data,
+};
+use futures_util::future::{LocalBoxFuture, Ready, ready};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum RateLimitKey {
+    Ip,
+    Session,
+    Lobby,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RateLimit {
+    // requests are counted per client ip, per wim_session_id cookie (client ip without cookie) or
+    // per lobby
+    key: RateLimitKey,
+
+    // maximum number of requests at once
+    burst: u32,
+
+    // number of requests refilled per second
+    per_sec: f64,
+
+    // maximum number of tracked keys, the least recently used bucket is replaced by a new one
+    #[serde(default = "default_max_entries")]
+    max_entries: usize,
+
+    #[serde(skip)]
+    buckets: Buckets,
+}
+
+fn default_max_entries() -> usize {
+    100000
+}
+
+impl RateLimit {
+    /// Takes one request out of the bucket of the caller or returns the time until the next one
+    /// is available. Requests without the key (e.g. unknown client address) aren't limited.
+    pub fn take<T: ParamTuple>(&self, req: &HttpRequest, params: &T) -> Result<(), Duration> {
+        let ip = || client_ip(req).map(ip_key);
+        let key = match self.key {
+            RateLimitKey::Ip => ip(),
+            // a client can always drop its cookie
+            RateLimitKey::Session => get_session_id(req).map(|id| id.to_string()).or_else(ip),
+            RateLimitKey::Lobby => Some(params.scope().lobby_id.to_string()),
+        };
+        match key {
+            Some(key) => self.buckets.take(key, self),
+            None => Ok(()),
+        }
+    }
+}
+
+/// IPv6 clients usually get a whole /64, so its addresses share one bucket
+fn ip_key(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => {
+            let prefix = u128::from(ip) & !u128::from(u64::MAX);
+            format!("{}/64", Ipv6Addr::from(prefix))
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let refilled = now.duration_since(self.updated).as_secs_f64() * limit.per_sec;
+        self.tokens = (self.tokens + refilled).min(limit.burst as f64);
+        self.updated = now;
+    }
+}
+
+#[derive(Clone, Default)]
+struct Buckets(Arc<Mutex<BucketMap>>);
+
+/// Buckets by key, with an index ordered by their last use
+#[derive(Default)]
+struct BucketMap {
+    buckets: HashMap<String, Bucket>,
+    by_update: BTreeSet<(Instant, String)>,
+}
+
+impl fmt::Debug for Buckets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Buckets")
+    }
+}
+
+impl Buckets {
+    fn take(&self, key: String, limit: &RateLimit) -> Result<(), Duration> {
+        let mut map = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        let BucketMap {
+            buckets,
+            by_update,
+        } = &mut *map;
+        let now = Instant::now();
+        match buckets.get(&key) {
+            Some(bucket) => {
+                by_update.remove(&(bucket.updated, key.clone()));
+            }
+            // full: the bucket unused for the longest time makes room, new keys stay limited
+            None if buckets.len() >= limit.max_entries.max(1) => {
+                if let Some((_, oldest)) = by_update.pop_first() {
+                    buckets.remove(&oldest);
+                }
+            }
+            None => {}
+        }
+
+        let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        });
+        bucket.refill(limit, now);
+        by_update.insert((now, key));
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait = (1.0 - bucket.tokens) / limit.per_sec;
+        Err(Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX))
+    }
+}
+
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
+
+pub fn rate_limited_msg(retry_after: Duration) -> String {
+    let secs = retry_after_secs(retry_after);
+    format!("Too many requests, try again in {secs} seconds")
+}
+
+/// `429` response, the websocket session of the caller gets a warning too
+pub fn too_many_requests(req: &HttpRequest, retry_after: Duration) -> HttpResponse {
+    let secs = retry_after_secs(retry_after);
+    let msg = rate_limited_msg(retry_after);
+    if let (Some(session_id), Some(notify)) = (
+        get_session_id(req),
+        req.app_data::<Data<Addr<NotifyServer>>>(),
+    ) {
+        notify.do_send(SystemNotification::new(
+            session_id,
+            msg.clone(),
+            SystemNotificationType::Warning,
+        ));
+    }
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, secs.to_string()))
+        .body(msg)
+}
+
+/// Marks a request whose rate limits of the action were taken by the `RateLimited` middleware
+pub struct RateLimitsTaken(pub Action);
+
+/// Takes the rate limits of the action before the handler reads the request body, so a limited
+/// client can't make the server receive a large upload first. Only works on routes with a
+/// `{lobby_id}` or `{upload_id}`, the others are limited when their permission is checked.
+pub struct RateLimited(pub Action);
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimited
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitedService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitedService {
+            service,
+            action: self.0,
+        }))
+    }
+}
+
+pub struct RateLimitedService<S> {
+    service: S,
+    action: Action,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitedService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        match take_route_limits(self.action, req.request()) {
+            Some(Err(retry_after)) => {
+                let res = too_many_requests(req.request(), retry_after);
+                return Box::pin(ready(Ok(req.into_response(res).map_into_right_body())));
+            }
+            Some(Ok(())) => {
+                req.extensions_mut().insert(RateLimitsTaken(self.action));
+            }
+            None => {}
+        }
+        let res = self.service.call(req);
+        Box::pin(async move { res.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
+
+/// Rate limits for the ids of the route, `None` if the route has neither a lobby nor an upload
+/// id. Chunks count for the lobby and room of their upload, unknown uploads for the nil lobby.
+fn take_route_limits(action: Action, req: &HttpRequest) -> Option<Result<(), Duration>> {
+    let cfg = req.app_data::<Data<ServerConfig>>()?;
+    let permissions = &cfg.permissions;
+    let info = req.match_info();
+    if let Some(upload_id) = info.get("upload_id") {
+        let session = UploadId::parse_str(upload_id)
+            .ok()
+            .and_then(|upload_id| read_session(&cfg.chunked_upload, &upload_id));
+        return Some(match session {
+            Some((session, _)) => {
+                limit_rate(permissions, action, req, &(session.lobby_id, session.room_id))
+            }
+            None => limit_rate(permissions, action, req, &(LobbyId::nil(),)),
+        });
+    }
+    let lobby_id = LobbyId::parse_str(info.get("lobby_id")?).ok()?;
+    let room_id = info.get("room_id").and_then(|id| id.parse::<RoomId>().ok());
+    let img_id = info
+        .get("img_id")
+        .or_else(|| info.get("file"))
+        .and_then(|id| id.parse::<ImgId>().ok());
+    Some(match (room_id, img_id) {
+        (Some(room_id), Some(img_id)) => {
+            limit_rate(permissions, action, req, &(lobby_id, room_id, img_id))
+        }
+        (Some(room_id), None) => limit_rate(permissions, action, req, &(lobby_id, room_id)),
+        _ => limit_rate(permissions, action, req, &(lobby_id,)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunked_upload::{UploadSession, create_session};
+    use actix_web::test::TestRequest;
+    use std::{env, fs};
+    use uuid::Uuid;
+
+    fn limit(key: RateLimitKey, burst: u32, max_entries: usize) -> RateLimit {
+        RateLimit {
+            key,
+            burst,
+            per_sec: 0.001,
+            max_entries,
+            buckets: Buckets::default(),
+        }
+    }
+
+    fn take(limit: &RateLimit, peer: &str) -> Result<(), Duration> {
+        let req = TestRequest::default()
+            .peer_addr(peer.parse().unwrap())
+            .to_http_request();
+        limit.take(&req, &(Uuid::nil(),))
+    }
+
+    #[test]
+    fn limits_per_ip() {
+        let limit = limit(RateLimitKey::Ip, 2, 100);
+        assert!(take(&limit, "192.0.2.1:1000").is_ok());
+        assert!(take(&limit, "192.0.2.1:1001").is_ok());
+        assert!(take(&limit, "192.0.2.1:1002").is_err());
+        assert!(take(&limit, "192.0.2.2:1000").is_ok());
+    }
+
+    #[test]
+    fn ipv6_addresses_of_one_prefix_share_a_bucket() {
+        let limit = limit(RateLimitKey::Ip, 1, 100);
+        assert!(take(&limit, "[2001:db8:1:2::1]:1000").is_ok());
+        assert!(take(&limit, "[2001:db8:1:2:ffff:ffff:ffff:ffff]:1000").is_err());
+        assert!(take(&limit, "[2001:db8:1:3::1]:1000").is_ok());
+    }
+
+    #[test]
+    fn replaces_the_least_recently_used_bucket_when_full() {
+        let limit = limit(RateLimitKey::Ip, 1, 2);
+        assert!(take(&limit, "192.0.2.1:1000").is_ok());
+        assert!(take(&limit, "192.0.2.2:1000").is_ok());
+        assert!(take(&limit, "192.0.2.1:1000").is_err());
+
+        // new keys are still limited, the bucket of .2 was used longest ago and is replaced
+        assert!(take(&limit, "192.0.2.3:1000").is_ok());
+        assert!(take(&limit, "192.0.2.3:1000").is_err());
+        assert!(take(&limit, "192.0.2.1:1000").is_err());
+        assert_eq!(limit.buckets.0.lock().unwrap().buckets.len(), 2);
+        assert_eq!(limit.buckets.0.lock().unwrap().by_update.len(), 2);
+    }
+
+    #[test]
+    fn limits_chunks_for_the_lobby_of_their_upload() {
+        let dir = env::temp_dir().join(format!("wim-chunks-{}", Uuid::new_v4()));
+        let mut cfg = ServerConfig::default();
+        cfg.chunked_upload.temp_path = dir.to_string_lossy().to_string();
+        let limits = vec![limit(RateLimitKey::Lobby, 1, 100)];
+        cfg.permissions.rate_limits.insert(Action::UploadImg, limits);
+        let session = UploadSession {
+            lobby_id: Uuid::new_v4(),
+            room_id: 1,
+            size: 10,
+            content_type: String::from("image/png"),
+            uploader_id: None,
+        };
+        let upload_id = create_session(&cfg.chunked_upload, &session).unwrap();
+        let cfg = Data::new(cfg);
+        let take = |upload_id: Uuid| {
+            let req = TestRequest::default()
+                .param("upload_id", upload_id.to_string())
+                .app_data(cfg.clone())
+                .to_http_request();
+            take_route_limits(Action::UploadImg, &req)
+        };
+
+        assert!(matches!(take(upload_id), Some(Ok(()))));
+        assert!(matches!(take(upload_id), Some(Err(_))));
+        // unknown uploads share the bucket of the nil lobby
+        assert!(matches!(take(Uuid::new_v4()), Some(Ok(()))));
+        assert!(matches!(take(Uuid::new_v4()), Some(Err(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
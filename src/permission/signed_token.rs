@@ -0,0 +1,194 @@
+use super::{Action, Refusal, read_token};
+use crate::{public_messages::permission::SignedTokenClaims, utils::ParamTuple};
+use actix_web::HttpRequest;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Tokens minted by another server with a shared secret. Format:
+/// `base64url(claims json).base64url(hmac-sha256(secret, base64url(claims json)))`
+#[derive(Deserialize, Clone, Debug)]
+pub struct SignedTokenCfg {
+    secret: String,
+
+    // query parameter to read the token from, if there is no Authorization header
+    #[serde(default = "default_query_param")]
+    query_param: String,
+}
+
+pub fn default_query_param() -> String {
+    String::from("token")
+}
+
+impl SignedTokenCfg {
+    pub fn is_allowed<T: ParamTuple>(
+        &self,
+        action: Action,
+        req: &HttpRequest,
+        params: &T,
+    ) -> Result<(), Refusal> {
+        let token = read_token(req, &self.query_param).ok_or("No token")?;
+        let claims = self.verify(&token)?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| Refusal::Failed(err.to_string()))?
+            .as_secs();
+        if claims.exp < now {
+            return Err("Token expired".into());
+        }
+        if !claims.actions.contains(&action) {
+            return Err(format!("Token not valid for {}", action.as_str()).into());
+        }
+
+        match params
+            .scope()
+            .is_within(claims.lobby_id, claims.room_id, claims.img_id)
+        {
+            true => Ok(()),
+            false => Err("Token not valid for this resource".into()),
+        }
+    }
+
+    /// Only a wrong signature is a denial, a token that can't be read is an error
+    fn verify(&self, token: &str) -> Result<SignedTokenClaims, Refusal> {
+        let failed = |msg: &str| Refusal::Failed(msg.to_string());
+        let (payload, signature) = token
+            .split_once('.')
+            .ok_or_else(|| failed("Malformed token"))?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| failed("Malformed token signature"))?;
+
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes())
+            .map_err(|err| failed(&err.to_string()))?;
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| "Invalid token signature")?;
+
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| failed("Malformed token payload"))?;
+        serde_json::from_slice(&payload)
+            .map_err(|err| failed(&format!("Invalid token claims: {err}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use uuid::Uuid;
+
+    const SECRET: &str = "test-secret";
+
+    fn cfg() -> SignedTokenCfg {
+        SignedTokenCfg {
+            secret: String::from(SECRET),
+            query_param: default_query_param(),
+        }
+    }
+
+    fn mint(secret: &str, claims: &SignedTokenClaims) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap());
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(payload.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{payload}.{signature}")
+    }
+
+    fn claims(lobby_id: Option<Uuid>, actions: Vec<Action>, exp: u64) -> SignedTokenClaims {
+        SignedTokenClaims {
+            lobby_id,
+            room_id: None,
+            img_id: None,
+            actions,
+            exp,
+        }
+    }
+
+    fn in_an_hour() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60 * 60
+    }
+
+    #[test]
+    fn verifies_the_signature() {
+        let lobby_id = Uuid::new_v4();
+        let token = mint(SECRET, &claims(Some(lobby_id), vec![Action::GetImgBig], 42));
+        let verified = cfg().verify(&token).unwrap();
+        assert_eq!(verified.lobby_id, Some(lobby_id));
+        assert_eq!(verified.actions, vec![Action::GetImgBig]);
+        assert_eq!(verified.exp, 42);
+
+        let token = mint("other-secret", &claims(None, vec![Action::GetImgBig], 42));
+        assert!(matches!(cfg().verify(&token), Err(Refusal::Denied(_))));
+    }
+
+    #[test]
+    fn denies_changed_claims() {
+        let token = mint(SECRET, &claims(None, vec![Action::GetImgBig], 42));
+        let (_, signature) = token.split_once('.').unwrap();
+        let changed = claims(None, vec![Action::DeleteLobby], 42);
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&changed).unwrap());
+        let token = format!("{payload}.{signature}");
+        assert!(matches!(cfg().verify(&token), Err(Refusal::Denied(_))));
+    }
+
+    #[test]
+    fn fails_on_unreadable_tokens() {
+        let failed = |token: &str| matches!(cfg().verify(token), Err(Refusal::Failed(_)));
+        assert!(failed("no-dot"));
+        assert!(failed("payload.!!!"));
+
+        // correctly signed, but not the claims
+        let payload = URL_SAFE_NO_PAD.encode(b"{\"exp\":\"soon\"}");
+        let mut mac = HmacSha256::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(payload.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        assert!(failed(&format!("{payload}.{signature}")));
+    }
+
+    #[test]
+    fn checks_expiry_action_and_scope() {
+        let lobby_id = Uuid::new_v4();
+        let is_allowed = |claims: &SignedTokenClaims, action, lobby_id| {
+            let req = TestRequest::default()
+                .uri(&format!("/img/1?token={}", mint(SECRET, claims)))
+                .to_http_request();
+            cfg().is_allowed(action, &req, &(lobby_id,)).is_ok()
+        };
+
+        let valid = claims(Some(lobby_id), vec![Action::GetImgBig], in_an_hour());
+        assert!(is_allowed(&valid, Action::GetImgBig, lobby_id));
+        assert!(!is_allowed(&valid, Action::DeleteImg, lobby_id));
+        assert!(!is_allowed(&valid, Action::GetImgBig, Uuid::new_v4()));
+
+        let expired = claims(Some(lobby_id), vec![Action::GetImgBig], 1);
+        assert!(!is_allowed(&expired, Action::GetImgBig, lobby_id));
+
+        let any_lobby = claims(None, vec![Action::GetImgBig], in_an_hour());
+        assert!(is_allowed(&any_lobby, Action::GetImgBig, Uuid::new_v4()));
+    }
+
+    #[test]
+    fn reads_the_authorization_header() {
+        let token = mint(SECRET, &claims(None, vec![Action::GetImgBig], in_an_hour()));
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_http_request();
+        let params = (Uuid::new_v4(),);
+        assert!(cfg().is_allowed(Action::GetImgBig, &req, &params).is_ok());
+
+        let req = TestRequest::default().to_http_request();
+        let res = cfg().is_allowed(Action::GetImgBig, &req, &params);
+        assert!(matches!(res, Err(Refusal::Denied(_))));
+    }
+}
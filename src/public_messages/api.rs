@@ -1,4 +1,8 @@
-use crate::{ImgId, LobbyId};
+use crate::{
+    chunked_upload::UploadId,
+    upload::{UploadError, Uploaded},
+    ImgId, LobbyId, RoomId,
+};
 use actix_multipart::form::{tempfile::TempFile, MultipartForm};
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
@@ -15,6 +19,134 @@ pub struct UploadRequest {
 #[ts(export)]
 pub struct UploadResult {
     pub img_id: ImgId,
+
+    // lets the uploader delete the image, missing for duplicates
+    pub owner_token: Option<String>,
+}
+
+impl From<Uploaded> for UploadResult {
+    fn from(uploaded: Uploaded) -> Self {
+        match uploaded {
+            Uploaded::New(img_id, owner_token) => Self {
+                img_id,
+                owner_token,
+            },
+            Uploaded::Duplicate(img_id) => Self {
+                img_id,
+                owner_token: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, MultipartForm, TS)]
+#[ts(export)]
+pub struct UploadBatchRequest {
+    #[multipart]
+    #[ts(type = "File[]")]
+    pub images: Vec<TempFile>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub enum UploadStatus {
+    Uploaded,
+    Duplicate,
+    Rejected,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct UploadBatchItemResult {
+    pub file_name: Option<String>,
+    pub status: UploadStatus,
+    pub img_id: Option<ImgId>,
+    pub owner_token: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl UploadBatchItemResult {
+    pub fn new(file_name: Option<String>, result: Result<Uploaded, UploadError>) -> Self {
+        let (status, img_id, owner_token, reason) = match result {
+            Ok(Uploaded::New(img_id, owner_token)) => {
+                (UploadStatus::Uploaded, Some(img_id), owner_token, None)
+            }
+            Ok(Uploaded::Duplicate(img_id)) => (UploadStatus::Duplicate, Some(img_id), None, None),
+            Err(err) => (
+                UploadStatus::Rejected,
+                None,
+                None,
+                Some(err.reason().to_string()),
+            ),
+        };
+        Self {
+            file_name,
+            status,
+            img_id,
+            owner_token,
+            reason,
+        }
+    }
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct UploadUrlRequest {
+    pub url: String,
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct UploadSessionRequest {
+    pub size: usize,
+    pub content_type: String,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct UploadSessionStatus {
+    pub upload_id: UploadId,
+    pub offset: usize,
+    pub size: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, TS)]
+#[ts(export)]
+pub struct ImgRef {
+    pub room_id: RoomId,
+    pub img_id: ImgId,
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct DeleteBatchRequest {
+    pub images: Vec<ImgRef>,
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct DeleteRoomBatchRequest {
+    pub img_ids: Vec<ImgId>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct DeleteBatchItemResult {
+    pub room_id: RoomId,
+    pub img_id: ImgId,
+    pub deleted: bool,
+    pub reason: Option<String>,
+}
+
+impl DeleteBatchItemResult {
+    pub fn new(img: ImgRef, result: Result<(), String>) -> Self {
+        Self {
+            room_id: img.room_id,
+            img_id: img.img_id,
+            deleted: result.is_ok(),
+            reason: result.err(),
+        }
+    }
 }
 
 #[derive(Serialize, TS)]
@@ -1,3 +1,4 @@
+use crate::{permission::Action, ImgId, LobbyId, RoomId};
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
@@ -6,4 +7,20 @@ use ts_rs::TS;
 pub struct ConfirmationResponse {
     pub is_allowed: bool,
     pub error_msg: Option<String>,
+
+    // name of the caller shown to the other lobby members, only read for the notifications identity
+    #[serde(default)]
+    #[ts(optional)]
+    pub display_name: Option<String>,
+}
+
+/// Content of a `SignedToken`. Ids left empty are not restricted, `exp` is a unix timestamp.
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct SignedTokenClaims {
+    pub lobby_id: Option<LobbyId>,
+    pub room_id: Option<RoomId>,
+    pub img_id: Option<ImgId>,
+    pub actions: Vec<Action>,
+    pub exp: u64,
 }
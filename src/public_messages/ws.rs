@@ -1,26 +1,44 @@
-use crate::{ImgId, RoomId, SessionId};
-use serde::Serialize;
+use crate::{public_messages::api::ImgRef, ImgId, RoomId, SessionId};
+use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 #[derive(Serialize, TS)]
 #[ts(export)]
-pub struct ConnectEvent {
+pub struct ConnectEvent<'a> {
     pub event: &'static str,
+    #[ts(type = "number")]
+    pub seq: u64,
     pub session_id: SessionId,
+
+    // pass as `session` query parameter to resume the session after a reconnect
+    pub session_token: &'a str,
 }
 
 #[derive(Serialize, TS)]
 #[ts(export)]
 pub struct ImageProcessedEvent {
     pub event: &'static str,
+    #[ts(type = "number")]
+    pub seq: u64,
     pub room_id: RoomId,
     pub img_id: ImgId,
 }
 
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct ImagesDeletedEvent<'a> {
+    pub event: &'static str,
+    #[ts(type = "number")]
+    pub seq: u64,
+    pub images: &'a [ImgRef],
+}
+
 #[derive(Serialize, TS)]
 #[ts(export)]
 pub struct RoomDeletedEvent {
     pub event: &'static str,
+    #[ts(type = "number")]
+    pub seq: u64,
     pub room_id: RoomId,
 }
 
@@ -28,12 +46,16 @@ pub struct RoomDeletedEvent {
 #[ts(export)]
 pub struct LobbyDeletedEvent {
     pub event: &'static str,
+    #[ts(type = "number")]
+    pub seq: u64,
 }
 
 #[derive(Serialize, TS)]
 #[ts(export)]
 pub struct ChatMessageEvent<'a> {
     pub event: &'static str,
+    #[ts(type = "number")]
+    pub seq: u64,
     pub username: &'a str,
     pub msg: &'a str,
 }
@@ -45,3 +67,100 @@ pub struct SystemNotificationEvent<'a> {
     pub msg: &'a str,
     pub msg_type: &'a str,
 }
+
+/// Sent on reconnect if the missed events are no longer available, the client has to reload
+/// everything and continues with `seq`
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct ResyncRequiredEvent {
+    pub event: &'static str,
+    #[ts(type = "number")]
+    pub seq: u64,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct TypingEvent {
+    pub event: &'static str,
+    pub session_id: SessionId,
+    pub room_id: Option<RoomId>,
+}
+
+/// Session of a lobby, sessions that lost their connection stay members for the grace period
+#[derive(Serialize, Clone, TS)]
+#[ts(export)]
+pub struct PresenceMember {
+    pub session_id: SessionId,
+    pub display_name: Option<String>,
+}
+
+/// Members of the lobby, sent after `Connected`
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct PresenceEvent {
+    pub event: &'static str,
+    pub members: Vec<PresenceMember>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct UserJoinedEvent<'a> {
+    pub event: &'static str,
+    pub session_id: SessionId,
+    pub display_name: Option<&'a str>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct UserLeftEvent {
+    pub event: &'static str,
+    pub session_id: SessionId,
+}
+
+/// Command sent by the client over the websocket. The `request_id` is repeated in the reply.
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct WsRequest {
+    pub request_id: Option<u32>,
+    #[serde(flatten)]
+    pub command: WsCommand,
+}
+
+#[derive(Deserialize, TS)]
+#[serde(tag = "cmd")]
+#[ts(export)]
+pub enum WsCommand {
+    SendChatMessage { msg: String },
+    Subscribe { room_id: RoomId },
+    Unsubscribe { room_id: RoomId },
+    Typing { room_id: Option<RoomId> },
+    GetRoomSnapshot { room_id: RoomId },
+    Ack {
+        #[ts(type = "number")]
+        seq: u64,
+    },
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct CommandAckEvent {
+    pub event: &'static str,
+    pub request_id: Option<u32>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct CommandErrorEvent<'a> {
+    pub event: &'static str,
+    pub request_id: Option<u32>,
+    pub msg: &'a str,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct RoomSnapshotEvent {
+    pub event: &'static str,
+    pub request_id: Option<u32>,
+    pub room_id: RoomId,
+    pub img_ids: Vec<ImgId>,
+}
@@ -0,0 +1,149 @@
+use crate::{
+    ImgId, LobbyId, RoomId, SessionId,
+    check::{ImgCheck, ImgChecker, check_image},
+    config::{CheckPhase, ServerConfig},
+    img::{OriginalImg, SaveImageResult, resize_image, save_img},
+    notification::{internal_messages::ImageUploaded, server::NotifyServer},
+    permission::owner::save_owner,
+};
+use actix::prelude::*;
+use actix_web::{HttpResponse, web};
+use log::warn;
+
+pub enum UploadError {
+    BadRequest(String),
+    Forbidden(String),
+    Internal(String),
+}
+
+impl UploadError {
+    pub fn reason(&self) -> &str {
+        match self {
+            UploadError::BadRequest(msg)
+            | UploadError::Forbidden(msg)
+            | UploadError::Internal(msg) => msg,
+        }
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        match self {
+            UploadError::BadRequest(msg) => HttpResponse::BadRequest().body(msg.clone()),
+            UploadError::Forbidden(msg) => HttpResponse::Forbidden().body(msg.clone()),
+            UploadError::Internal(msg) => HttpResponse::InternalServerError().body(msg.clone()),
+        }
+    }
+}
+
+pub enum Uploaded {
+    // new image with the owner token of the uploader
+    New(ImgId, Option<String>),
+    Duplicate(ImgId),
+}
+
+pub fn check_size(size: usize, cfg: &ServerConfig) -> Result<(), UploadError> {
+    match size {
+        0 => Err(UploadError::BadRequest(String::from("Empty image"))),
+        length if length > cfg.max_image_size_byte => Err(UploadError::BadRequest(format!(
+            "The uploaded file is too large. Maximum size is {} bytes.",
+            cfg.max_image_size_byte
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Resizes, checks, saves and announces an uploaded image. Shared by every upload route.
+pub async fn process_upload(
+    original: OriginalImg,
+    lobby_id: LobbyId,
+    room_id: RoomId,
+    uploader_id: Option<SessionId>,
+    cfg: &ServerConfig,
+    checker: &Addr<ImgChecker>,
+    notify: &Addr<NotifyServer>,
+) -> Result<Uploaded, UploadError> {
+    // Process image
+    let (img, thumb_img, original) = web::block(move || {
+        let img = original.decode()?;
+        let img = resize_image(img, 4000, 2000);
+        let thumb_img = resize_image(img.clone(), 600, 200);
+        Ok((img, thumb_img, original))
+    })
+    .await
+    .map_err(|err| UploadError::Internal(err.to_string()))?
+    .map_err(|err: &str| UploadError::BadRequest(err.into()))?;
+    let original = cfg.keeps_originals(&lobby_id).then_some(original);
+
+    // At upload check
+    if let Some(check) = &cfg.upload_check
+        && check.check_phase == CheckPhase::BeforeUpload
+    {
+        match check_image(&check.url, thumb_img.clone(), None).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err(UploadError::Forbidden(
+                    check
+                        .not_allowed_msg
+                        .clone()
+                        .unwrap_or_else(|| "This image is not allowed".into()),
+                ));
+            }
+            Err(err_msg) => {
+                return Err(UploadError::Internal(format!(
+                    "Upload check failed: {err_msg}"
+                )));
+            }
+        }
+    }
+
+    // Save images
+    let storage_path = cfg.images_storage_path.clone();
+    let (save_result, thumb_img) = web::block(move || {
+        let res = save_img(
+            &img,
+            &thumb_img,
+            original.as_ref(),
+            &lobby_id,
+            &room_id,
+            &storage_path,
+        );
+        (res, thumb_img)
+    })
+    .await
+    .map_err(|err| UploadError::Internal(err.to_string()))?;
+
+    let img_id = match save_result {
+        SaveImageResult::Ok(id) => id,
+        SaveImageResult::ImageAlreadyExists(img_id) => return Ok(Uploaded::Duplicate(img_id)),
+        SaveImageResult::Err(err_msg) => return Err(UploadError::Internal(err_msg)),
+    };
+
+    // Remember uploader, the image stays usable without owner
+    let owner_token = save_owner(
+        &cfg.images_storage_path,
+        (lobby_id, room_id, img_id),
+        uploader_id,
+    )
+    .inspect_err(|err| warn!("Can't save owner of img {img_id}: {err}"))
+    .ok();
+
+    // After upload check
+    if let Some(check) = &cfg.upload_check
+        && check.check_phase == CheckPhase::AfterUpload
+    {
+        checker.do_send(ImgCheck::new(
+            thumb_img,
+            lobby_id,
+            room_id,
+            img_id,
+            uploader_id,
+        ));
+    }
+
+    // Notify users
+    notify
+        .send(ImageUploaded::new(lobby_id, room_id, img_id))
+        .await
+        .unwrap_or_else(|err| warn!("Can't notify users: {}", err));
+
+    Ok(Uploaded::New(img_id, owner_token))
+}
@@ -0,0 +1,258 @@
+use crate::{config::ServerConfig, img::OriginalImg, upload::UploadError};
+use image::ImageFormat;
+use reqwest::{StatusCode, Url, header, redirect::Policy};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{net::lookup_host, time::timeout};
+
+/// Downloads an image for the upload pipeline. Every hop of a redirect chain is resolved and
+/// checked against private networks before the request is sent to the pinned address.
+pub async fn fetch_img(url: &str, cfg: &ServerConfig) -> Result<OriginalImg, UploadError> {
+    // the client timeout applies to every hop, this one to the whole redirect chain
+    let deadline = Duration::from_secs(cfg.url_upload.timeout_sec);
+    timeout(deadline, follow_redirects(url, cfg))
+        .await
+        .unwrap_or_else(|_| Err(bad_request(String::from("Fetching the image timed out"))))
+}
+
+async fn follow_redirects(url: &str, cfg: &ServerConfig) -> Result<OriginalImg, UploadError> {
+    let mut url = Url::parse(url).map_err(|err| bad_request(format!("Invalid url: {err}")))?;
+
+    for _ in 0..=cfg.url_upload.max_redirects {
+        let addr = resolve_public_addr(&url).await?;
+        let host = url.host_str().unwrap_or_default().to_string();
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            // a proxy would connect to the host itself, past the checked address
+            .no_proxy()
+            .timeout(Duration::from_secs(cfg.url_upload.timeout_sec))
+            .resolve(&host, addr)
+            .build()
+            .map_err(|err| UploadError::Internal(err.to_string()))?;
+        let res = client
+            .get(url.clone())
+            .send()
+            .await
+            .map_err(|err| bad_request(format!("Can't fetch image: {err}")))?;
+
+        if res.status().is_redirection() {
+            let location = res
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or_else(|| bad_request("Redirect without location".into()))?;
+            url = url
+                .join(location)
+                .map_err(|err| bad_request(format!("Invalid redirect: {err}")))?;
+            continue;
+        }
+
+        return read_response(res, cfg).await;
+    }
+
+    Err(bad_request(String::from("Too many redirects")))
+}
+
+async fn read_response(
+    mut res: reqwest::Response,
+    cfg: &ServerConfig,
+) -> Result<OriginalImg, UploadError> {
+    if res.status() != StatusCode::OK {
+        return Err(bad_request(format!(
+            "Image server answered {}",
+            res.status()
+        )));
+    }
+
+    let mime_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime_type| mime_type.trim().to_lowercase())
+        .ok_or_else(|| bad_request("Can't read image format".into()))?;
+    let format = mime_type
+        .starts_with("image/")
+        .then(|| ImageFormat::from_mime_type(&mime_type))
+        .flatten()
+        .ok_or_else(|| bad_request("Unknown image format".into()))?;
+
+    let too_large = || {
+        bad_request(format!(
+            "The image is too large. Maximum size is {} bytes.",
+            cfg.max_image_size_byte
+        ))
+    };
+    if res
+        .content_length()
+        .is_some_and(|len| len as usize > cfg.max_image_size_byte)
+    {
+        return Err(too_large());
+    }
+
+    // Content-Length can be missing or wrong, so count while reading
+    let mut bytes = Vec::new();
+    while let Some(chunk) = res
+        .chunk()
+        .await
+        .map_err(|err| bad_request(format!("Can't read image: {err}")))?
+    {
+        if bytes.len() + chunk.len() > cfg.max_image_size_byte {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    if bytes.is_empty() {
+        return Err(bad_request(String::from("Empty image")));
+    }
+
+    Ok(OriginalImg::new(bytes, format))
+}
+
+async fn resolve_public_addr(url: &Url) -> Result<SocketAddr, UploadError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(bad_request(String::from(
+            "Only http and https urls allowed",
+        )));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| bad_request(String::from("Url without host")))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addrs: Vec<SocketAddr> = lookup_host((host, port))
+        .await
+        .map_err(|err| bad_request(format!("Can't resolve {host}: {err}")))?
+        .collect();
+
+    // Reject the url if any address is private, so DNS round robin can't sneak one in
+    match addrs.first() {
+        Some(_) if addrs.iter().any(|addr| !is_public_ip(&addr.ip())) => Err(
+            UploadError::Forbidden(String::from("Url points to a private network")),
+        ),
+        Some(addr) => Ok(*addr),
+        None => Err(bad_request(format!("Can't resolve {host}"))),
+    }
+}
+
+pub fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ipv4(&ip),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+fn is_public_ipv4(ip: &Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || a == 0
+        || a >= 240
+        || (a == 100 && (64..128).contains(&b)) // shared address space
+        || (a == 192 && b == 0 && c == 0) // protocol assignments
+        || (a == 198 && (18..20).contains(&b))) // benchmarking
+}
+
+fn is_public_ipv6(ip: &Ipv6Addr) -> bool {
+    let [first, second, ..] = ip.segments();
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || (first & 0xfe00) == 0xfc00 // unique local
+        || (first & 0xffc0) == 0xfe80 // link local
+        || first == 0x2001 && second == 0xdb8 // documentation
+        || ip.segments()[..6] == [0; 6] // ipv4 compatible
+        // tunnels and translators reach ipv4 addresses, which might be private
+        || first == 0x64 && second == 0xff9b // nat64
+        || first == 0x2002 // 6to4
+        || first == 0x2001 && second == 0) // teredo
+}
+
+fn bad_request(msg: String) -> UploadError {
+    UploadError::BadRequest(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_private_addresses() {
+        let private = [
+            "0.0.0.0",
+            "0.1.2.3",
+            "10.1.2.3",
+            "100.64.0.1",
+            "100.127.255.254",
+            "127.0.0.1",
+            "169.254.169.254",
+            "172.16.0.1",
+            "172.31.255.255",
+            "192.0.0.1",
+            "192.0.2.1",
+            "192.168.1.1",
+            "198.18.0.1",
+            "198.51.100.1",
+            "203.0.113.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "::127.0.0.1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "::ffff:169.254.169.254",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "febf::1",
+            "ff02::1",
+            "2001:db8::1",
+            "64:ff9b::7f00:1",
+            "64:ff9b::a00:1",
+            "64:ff9b:1::1",
+            "2002:7f00:1::1",
+            "2002:c0a8:101::1",
+            "2001:0:4136:e378::1",
+        ];
+        for ip in private {
+            assert!(!is_public_ip(&ip.parse().unwrap()), "{ip} should be blocked");
+        }
+    }
+
+    #[test]
+    fn allows_public_addresses() {
+        let public = [
+            "1.1.1.1",
+            "8.8.8.8",
+            "100.63.255.255",
+            "100.128.0.1",
+            "172.15.255.255",
+            "172.32.0.1",
+            "192.0.3.1",
+            "192.169.0.1",
+            "198.20.0.1",
+            "223.255.255.254",
+            "::ffff:8.8.8.8",
+            "2001:4860:4860::8888",
+            "2606:4700::1111",
+            "2a00:1450:4001::1",
+            "fec0::1",
+        ];
+        for ip in public {
+            assert!(is_public_ip(&ip.parse().unwrap()), "{ip} should be allowed");
+        }
+    }
+}
@@ -1,11 +1,15 @@
-use crate::{ImgId, LobbyId, RoomId, SessionId};
-use actix_web::HttpRequest;
+use crate::{
+    config::ServerConfig, notification::session::verify_session_token, ImgId, LobbyId, RoomId,
+    SessionId,
+};
+use actix_web::{web::Data, HttpRequest};
 use serde_json::{from_value, Value};
 use std::{
     collections::HashMap,
     fs::{self, DirEntry},
     io::Error,
     path::PathBuf,
+    sync::OnceLock,
 };
 
 pub trait ToOutputJsonString {
@@ -22,7 +26,7 @@ pub fn get_foldernames_as_uuid(folder_path: &PathBuf) -> Vec<RoomId> {
     fs::read_dir(folder_path)
         .ok()
         .map(|entries| entries.filter_map(entry_to_room_id).collect())
-        .unwrap_or_else(Vec::new)
+        .unwrap_or_default()
 }
 
 pub fn rename_with_value<T: Into<Value>>(map: &mut HashMap<String, Value>, key: &str, val: T) {
@@ -36,14 +40,67 @@ pub fn rename_with_value<T: Into<Value>>(map: &mut HashMap<String, Value>, key:
     }
 }
 
+/// Matches `text` against `pattern`, where `*` stands for any number of characters
+pub fn matches_wildcard(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = text.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Ids of the resource a request is accessing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Scope {
+    pub lobby_id: LobbyId,
+    pub room_id: Option<RoomId>,
+    pub img_id: Option<ImgId>,
+}
+
+impl Scope {
+    /// True if every given id matches the scope, `None` stands for any id.
+    pub fn is_within(
+        &self,
+        lobby_id: Option<LobbyId>,
+        room_id: Option<RoomId>,
+        img_id: Option<ImgId>,
+    ) -> bool {
+        fn covers<T: PartialEq>(allowed: Option<T>, value: Option<T>) -> bool {
+            allowed.is_none() || allowed == value
+        }
+        covers(lobby_id, Some(self.lobby_id))
+            && covers(room_id, self.room_id)
+            && covers(img_id, self.img_id)
+    }
+}
+
 pub trait ParamTuple {
     fn edit_param_map(&self, map: &mut HashMap<String, Value>);
+    fn scope(&self) -> Scope;
 }
 
 impl ParamTuple for (LobbyId,) {
     fn edit_param_map(&self, map: &mut HashMap<String, Value>) {
         rename_with_value(map, "lobby_id", self.0.to_string());
     }
+
+    fn scope(&self) -> Scope {
+        Scope {
+            lobby_id: self.0,
+            room_id: None,
+            img_id: None,
+        }
+    }
 }
 
 impl ParamTuple for (LobbyId, RoomId) {
@@ -51,6 +108,14 @@ impl ParamTuple for (LobbyId, RoomId) {
         rename_with_value(map, "lobby_id", self.0.to_string());
         rename_with_value(map, "room_id", self.1);
     }
+
+    fn scope(&self) -> Scope {
+        Scope {
+            lobby_id: self.0,
+            room_id: Some(self.1),
+            img_id: None,
+        }
+    }
 }
 
 impl ParamTuple for (LobbyId, RoomId, ImgId) {
@@ -59,11 +124,54 @@ impl ParamTuple for (LobbyId, RoomId, ImgId) {
         rename_with_value(map, "room_id", self.1);
         rename_with_value(map, "img_id", self.2);
     }
+
+    fn scope(&self) -> Scope {
+        Scope {
+            lobby_id: self.0,
+            room_id: Some(self.1),
+            img_id: Some(self.2),
+        }
+    }
+}
+
+/// One pooled client for all outgoing requests
+pub fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
 }
 
 pub const SESSION_COOKIE_NAME: &str = "wim_session_id";
 
+/// Websocket session of the caller, only cookies signed by this server are accepted
 pub fn get_session_id(req: &HttpRequest) -> Option<SessionId> {
+    let cfg = req.app_data::<Data<ServerConfig>>()?;
     req.cookie(SESSION_COOKIE_NAME)
-        .and_then(|cookie| SessionId::parse_str(cookie.value()).ok())
+        .and_then(|cookie| verify_session_token(&cfg.notifications, cookie.value()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_matches_any_number_of_characters() {
+        assert!(matches_wildcard("*.example.com", "app.example.com"));
+        assert!(matches_wildcard("*.example.com", "a.b.example.com"));
+        assert!(matches_wildcard("app-*.example.com", "app-1.example.com"));
+        assert!(matches_wildcard("*", ""));
+        assert!(matches_wildcard("a*b*c", "abc"));
+        assert!(matches_wildcard("a*b*c", "a-b-b-c"));
+    }
+
+    #[test]
+    fn wildcard_needs_the_whole_text() {
+        assert!(matches_wildcard("example.com", "example.com"));
+        assert!(!matches_wildcard("example.com", "app.example.com"));
+        assert!(!matches_wildcard("*.example.com", "example.com"));
+        assert!(!matches_wildcard("*.example.com", "app.example.com.evil.org"));
+        assert!(!matches_wildcard("*.example.com", "evilexample.com"));
+        assert!(!matches_wildcard("a*b*c", "acb"));
+        // the parts around `*` can't overlap
+        assert!(!matches_wildcard("ab*bc", "abc"));
+    }
 }
@@ -10,7 +10,8 @@ const readFileWithoutFirstLine = (filePath) => {
       if (err) return reject(err);
       const lines = data.split('\n');
       lines.shift(); // Skip first line
-      resolve(lines.join('\n'));
+      // Types are combined into one file, so imports between them are not needed
+      resolve(lines.filter((line) => !line.startsWith('import type')).join('\n'));
     });
   });
 };
@@ -1,26 +1,58 @@
 import { EventEmitter } from './event-emitter';
 import {
   ChatMessageEvent,
+  CommandErrorEvent,
   ImageProcessedEvent,
+  ImagesDeletedEvent,
   LobbyDeletedEvent,
+  PresenceEvent,
+  ResyncRequiredEvent,
   RoomDeletedEvent,
+  RoomSnapshotEvent,
   SystemNotificationEvent,
+  TypingEvent,
+  UserJoinedEvent,
+  UserLeftEvent,
+  WsCommand,
 } from './rs-bindings';
-import { LobbyId } from './web-img-manager';
+import { LobbyId, RoomId } from './web-img-manager';
 
 export type NotificationsProtocol = 'ws' | 'wss';
 
 /** Class for communication with web img manager web socket server. */
 export class Notifications {
   emitter = new EventEmitter();
+  /** `seq` of the last received event, pass it as `since` when reconnecting */
+  last_seq?: number;
+  /** Token of this session, pass it as `session` when reconnecting to keep the session */
+  session_token?: string;
+  private socket: WebSocket;
+  private next_request_id = 1;
+  private pending = new Map<
+    number,
+    { resolve: (ev: any) => void; reject: (err: Error) => void }
+  >();
 
   constructor(
     server_addr: string,
     lobby_id: LobbyId,
-    protocol: NotificationsProtocol = 'ws'
+    protocol: NotificationsProtocol = 'ws',
+    room_id?: RoomId,
+    since?: number,
+    session?: string,
+    name?: string
   ) {
-    const url = `${protocol}://${server_addr}/notifications/${lobby_id}`;
+    let url = `${protocol}://${server_addr}/notifications/${lobby_id}`;
+    if (room_id != null) url += `/${room_id}`;
+    const query = new URLSearchParams();
+    if (since != null) query.set('since', `${since}`);
+    if (session != null) query.set('session', session);
+    if (name != null) query.set('name', name);
+    if (query.toString()) url += `?${query}`;
+    this.last_seq = since;
+    this.session_token = session;
     const socket = new WebSocket(url);
+    this.socket = socket;
 
     // Declare Events
     socket.addEventListener('open', (event) => {
@@ -37,10 +69,49 @@ export class Notifications {
 
     socket.addEventListener('message', (event) => {
       const evData = JSON.parse(event.data);
+      if (typeof evData.seq === 'number') this.last_seq = evData.seq;
+      if (evData.event === 'Connected') this.session_token = evData.session_token;
+      const pending =
+        evData.request_id != null && this.pending.get(evData.request_id);
+      if (pending) {
+        this.pending.delete(evData.request_id);
+        if (evData.event === 'CommandError')
+          pending.reject(new Error((evData as CommandErrorEvent).msg));
+        else pending.resolve(evData);
+      }
       this.emitter.emit(evData.event, evData);
     });
   }
 
+  /** Sends a command over the socket, resolves with the reply of the server */
+  send<TRes = void>(command: WsCommand): Promise<TRes> {
+    const request_id = this.next_request_id++;
+    return new Promise((resolve, reject) => {
+      this.pending.set(request_id, { resolve, reject });
+      this.socket.send(JSON.stringify({ request_id, ...command }));
+    });
+  }
+
+  sendChatMessage(msg: string): Promise<void> {
+    return this.send({ cmd: 'SendChatMessage', msg });
+  }
+
+  subscribe(room_id: RoomId): Promise<void> {
+    return this.send({ cmd: 'Subscribe', room_id });
+  }
+
+  unsubscribe(room_id: RoomId): Promise<void> {
+    return this.send({ cmd: 'Unsubscribe', room_id });
+  }
+
+  typing(room_id: RoomId | null = null): Promise<void> {
+    return this.send({ cmd: 'Typing', room_id });
+  }
+
+  getRoomSnapshot(room_id: RoomId): Promise<RoomSnapshotEvent> {
+    return this.send<RoomSnapshotEvent>({ cmd: 'GetRoomSnapshot', room_id });
+  }
+
   onConnected(handler: (ev: Event) => void): this {
     this.emitter.on('Connected', handler);
     return this;
@@ -76,6 +147,11 @@ export class Notifications {
     return this;
   }
 
+  onImagesDeleted(handler: (ev: ImagesDeletedEvent) => void): this {
+    this.emitter.on('ImagesDeleted', handler);
+    return this;
+  }
+
   onChatMessage(handler: (ev: ChatMessageEvent) => void): this {
     this.emitter.on('ChatMessage', handler as any);
     return this;
@@ -85,4 +161,29 @@ export class Notifications {
     this.emitter.on('SystemNotification', handler);
     return this;
   }
+
+  onResyncRequired(handler: (ev: ResyncRequiredEvent) => void): this {
+    this.emitter.on('ResyncRequired', handler);
+    return this;
+  }
+
+  onPresence(handler: (ev: PresenceEvent) => void): this {
+    this.emitter.on('Presence', handler);
+    return this;
+  }
+
+  onUserJoined(handler: (ev: UserJoinedEvent) => void): this {
+    this.emitter.on('UserJoined', handler);
+    return this;
+  }
+
+  onUserLeft(handler: (ev: UserLeftEvent) => void): this {
+    this.emitter.on('UserLeft', handler);
+    return this;
+  }
+
+  onTyping(handler: (ev: TypingEvent) => void): this {
+    this.emitter.on('Typing', handler);
+    return this;
+  }
 }
@@ -1,23 +1,78 @@
 
-export type ChatMessageEvent = { event: string, username: string, msg: string, };
+/**
+ * Every api call guarded by a permission, named like the matching `Permissions` field
+ */
+export type Action = "get_room_list" | "get_room_img_list" | "get_img_thumb" | "get_img_big" | "get_img_original" | "upload_img" | "delete_lobby" | "delete_room" | "delete_img" | "send_chat_message" | "get_presence" | "connect_notifications";
+
+
+export type ChatMessageEvent = { event: string, seq: number, username: string, msg: string, };
 
 
 export type ChatMessageRequest = { lobby_id: string, msg: string, };
 
 
-export type ConfirmationResponse = { is_allowed: boolean, error_msg: string | null, };
+export type CommandAckEvent = { event: string, request_id: number | null, };
+
+
+export type CommandErrorEvent = { event: string, request_id: number | null, msg: string, };
+
+
+export type ConfirmationResponse = { is_allowed: boolean, error_msg: string | null, display_name?: string, };
+
+
+export type ConnectEvent = { event: string, seq: number, session_id: string, session_token: string, };
+
+
+export type DeleteBatchItemResult = { room_id: number, img_id: number, deleted: boolean, reason: string | null, };
+
+
+export type DeleteBatchRequest = { images: Array<ImgRef>, };
+
+
+export type DeleteRoomBatchRequest = { img_ids: Array<number>, };
+
+
+export type ImageProcessedEvent = { event: string, seq: number, room_id: number, img_id: number, };
+
+
+export type ImagesDeletedEvent = { event: string, seq: number, images: Array<ImgRef>, };
+
+
+export type ImgRef = { room_id: number, img_id: number, };
+
+
+export type LobbyDeletedEvent = { event: string, seq: number, };
+
+
+/**
+ * Members of the lobby, sent after `Connected`
+ */
+export type PresenceEvent = { event: string, members: Array<PresenceMember>, };
 
 
-export type ConnectEvent = { event: string, session_id: string, };
+/**
+ * Session of a lobby, sessions that lost their connection stay members for the grace period
+ */
+export type PresenceMember = { session_id: string, display_name: string | null, };
 
 
-export type ImageProcessedEvent = { event: string, room_id: number, img_id: number, };
+/**
+ * Sent on reconnect if the missed events are no longer available, the client has to reload
+ * everything and continues with `seq`
+ */
+export type ResyncRequiredEvent = { event: string, seq: number, };
 
 
-export type LobbyDeletedEvent = { event: string, };
+export type RoomDeletedEvent = { event: string, seq: number, room_id: number, };
 
 
-export type RoomDeletedEvent = { event: string, room_id: number, };
+export type RoomSnapshotEvent = { event: string, request_id: number | null, room_id: number, img_ids: Array<number>, };
+
+
+/**
+ * Content of a `SignedToken`. Ids left empty are not restricted, `exp` is a unix timestamp.
+ */
+export type SignedTokenClaims = { lobby_id: string | null, room_id: number | null, img_id: number | null, actions: Array<Action>, exp: bigint, };
 
 
 export type Success = null;
@@ -26,8 +81,44 @@ export type Success = null;
 export type SystemNotificationEvent = { event: string, msg: string, msg_type: string, };
 
 
+export type TypingEvent = { event: string, session_id: string, room_id: number | null, };
+
+
+export type UploadBatchItemResult = { file_name: string | null, status: UploadStatus, img_id: number | null, owner_token: string | null, reason: string | null, };
+
+
+export type UploadBatchRequest = { images: File[], };
+
+
 export type UploadRequest = { image: File, };
 
 
-export type UploadResult = { img_id: number, };
+export type UploadResult = { img_id: number, owner_token: string | null, };
+
+
+export type UploadSessionRequest = { size: number, content_type: string, };
+
+
+export type UploadSessionStatus = { upload_id: string, offset: number, size: number, };
+
+
+export type UploadStatus = "Uploaded" | "Duplicate" | "Rejected";
+
+
+export type UploadUrlRequest = { url: string, };
+
+
+export type UserJoinedEvent = { event: string, session_id: string, display_name: string | null, };
+
+
+export type UserLeftEvent = { event: string, session_id: string, };
+
+
+export type WsCommand = { "cmd": "SendChatMessage", msg: string, } | { "cmd": "Subscribe", room_id: number, } | { "cmd": "Unsubscribe", room_id: number, } | { "cmd": "Typing", room_id: number | null, } | { "cmd": "GetRoomSnapshot", room_id: number, } | { "cmd": "Ack", seq: number, };
+
+
+/**
+ * Command sent by the client over the websocket. The `request_id` is repeated in the reply.
+ */
+export type WsRequest = { request_id: number | null, } & ({ "cmd": "SendChatMessage", msg: string, } | { "cmd": "Subscribe", room_id: number, } | { "cmd": "Unsubscribe", room_id: number, } | { "cmd": "Typing", room_id: number | null, } | { "cmd": "GetRoomSnapshot", room_id: number, } | { "cmd": "Ack", seq: number, });
 
@@ -1,5 +1,12 @@
 import { Notifications, NotificationsProtocol } from './notifications';
-import { Success, UploadResult } from './rs-bindings';
+import {
+  DeleteBatchItemResult,
+  ImgRef,
+  PresenceMember,
+  Success,
+  UploadBatchItemResult,
+  UploadResult,
+} from './rs-bindings';
 
 /**
  * @fileOverview Bindings for web img manager
@@ -31,6 +38,10 @@ export class WebImgManager {
     return `${this.protocol}://${this.server_addr}/img/${lobby_id}/${room_id}/${img_id}`;
   }
 
+  original_img_src(lobby_id: LobbyId, room_id: RoomId, img_id: ImgId): string {
+    return `${this.protocol}://${this.server_addr}/img/original/${lobby_id}/${room_id}/${img_id}`;
+  }
+
   thumb_img_src(lobby_id: LobbyId, room_id: RoomId, img_id: ImgId): string {
     return `${this.protocol}://${this.server_addr}/img/thumb/${lobby_id}/${room_id}/${img_id}`;
   }
@@ -53,15 +64,62 @@ export class WebImgManager {
     return response.json();
   }
 
+  async upload_img_from_url(
+    lobby_id: LobbyId,
+    room_id: RoomId,
+    img_url: string
+  ): Promise<UploadResult> {
+    const url = `${this.protocol}://${this.server_addr}/upload-url/${lobby_id}/${room_id}`;
+    return this.send(url, 'POST', { url: img_url });
+  }
+
+  async upload_img_batch(
+    lobby_id: LobbyId,
+    room_id: RoomId,
+    images: File[]
+  ): Promise<UploadBatchItemResult[]> {
+    const url = `${this.protocol}://${this.server_addr}/upload-batch/${lobby_id}/${room_id}`;
+    const formData = new FormData();
+    for (const image of images) formData.append('images', image);
+
+    const response = await fetch(url, {
+      method: 'POST',
+      body: formData,
+    });
+
+    if (response.ok !== true) throw Error(await response.text());
+    return response.json();
+  }
+
   async delete(
     lobby_id: LobbyId,
     room_id?: RoomId,
-    img_id?: ImgId
+    img_id?: ImgId,
+    owner_token?: string
   ): Promise<Success> {
     let url = `${this.protocol}://${this.server_addr}/delete/${lobby_id}`;
     if (room_id != null) url += `/${room_id}`;
     if (room_id != null && img_id != null) url += `/${img_id}`;
-    return this.send(url, 'POST');
+    const headers: Record<string, string> = {};
+    if (owner_token != null) headers['X-Owner-Token'] = owner_token;
+    return this.send(url, 'POST', undefined, headers);
+  }
+
+  async delete_batch(
+    lobby_id: LobbyId,
+    images: ImgRef[]
+  ): Promise<DeleteBatchItemResult[]> {
+    const url = `${this.protocol}://${this.server_addr}/delete-batch/${lobby_id}`;
+    return this.send(url, 'POST', { images });
+  }
+
+  async delete_room_batch(
+    lobby_id: LobbyId,
+    room_id: RoomId,
+    img_ids: ImgId[]
+  ): Promise<DeleteBatchItemResult[]> {
+    const url = `${this.protocol}://${this.server_addr}/delete-batch/${lobby_id}/${room_id}`;
+    return this.send(url, 'POST', { img_ids });
   }
 
   async sendChatMessage(lobby_id: LobbyId, msg: string): Promise<Success> {
@@ -69,26 +127,58 @@ export class WebImgManager {
     return this.send(url, 'POST', { lobby_id, msg });
   }
 
+  async get_presence(lobby_id: LobbyId): Promise<PresenceMember[]> {
+    return this.send(
+      `${this.protocol}://${this.server_addr}/presence/${lobby_id}`,
+      'GET'
+    );
+  }
+
+  /** Server-Sent Events alternative to `connect`, e.g. for `new EventSource(url)` */
+  events_src(
+    lobby_id: LobbyId,
+    since?: number,
+    session?: string,
+    name?: string
+  ): string {
+    const query = new URLSearchParams();
+    if (since != null) query.set('since', `${since}`);
+    if (session != null) query.set('session', session);
+    if (name != null) query.set('name', name);
+    const url = `${this.protocol}://${this.server_addr}/events/${lobby_id}`;
+    return query.toString() ? `${url}?${query}` : url;
+  }
+
   connect(
     lobby_id: LobbyId,
-    notifications_protocol: NotificationsProtocol = 'ws'
+    notifications_protocol: NotificationsProtocol = 'ws',
+    room_id?: RoomId,
+    since?: number,
+    session?: string,
+    name?: string
   ): Notifications {
     return new Notifications(
       this.server_addr,
       lobby_id,
-      notifications_protocol
+      notifications_protocol,
+      room_id,
+      since,
+      session,
+      name
     );
   }
 
   private async send<TRes>(
     url: string,
     method: string,
-    params?: object
+    params?: object,
+    headers?: Record<string, string>
   ): Promise<TRes> {
     const response = await fetch(url, {
       method: method,
       headers: {
         'Content-Type': 'application/json',
+        ...headers,
       },
       body: JSON.stringify(params),
     });